@@ -13,31 +13,421 @@ use syn::*;
 struct MirroredAction {
     pub function:      LitStr,
     pub args:          usize,
+    pub result:        bool,
+}
+
+/// Options parsed from the `#[reflect(...)]` attributes on a field.
+#[derive(Default)]
+struct FieldOptions {
+    pub skip:          bool,
+    pub readonly:      bool,
+    pub rename:        Option<String>,
+}
+
+/// Options parsed from the `#[reflect(...)]` attributes on a type.
+#[derive(Default)]
+struct ContainerOptions {
+    pub builder:       Option<String>,
+}
+
+/// The subset of `#[serde(...)]` attributes that changes the serialized shape of a value.
+/// Paths follow the serialized shape, so the derive has to honour these as well.
+#[derive(Default)]
+struct SerdeOptions {
+    pub rename:        Option<String>,
+    pub rename_all:    Option<String>,
+    pub flatten:       bool,
+    pub skip:          bool,
+    pub tag:           Option<String>,
+    pub content:       Option<String>,
+    pub untagged:      bool,
+}
+
+/// A field that can be reached through a path segment.
+struct ReflectField {
+    pub name:          String,
+    pub access:        proc_macro2::TokenStream,
+    pub readonly:      bool,
+    pub flatten:       bool,
+}
+
+fn field_options(attrs: &[Attribute]) -> FieldOptions {
+    let mut options = FieldOptions::default();
+
+    for attr in attrs.iter() {
+        if let Some(Meta::List(list)) = attr.interpret_meta() {
+            if list.ident == "reflect" {
+                for nest_meta in list.nested.iter() {
+                    match nest_meta {
+                        &NestedMeta::Meta(Meta::Word(ref word)) if word == "skip" => {
+                            options.skip = true;
+                        },
+                        &NestedMeta::Meta(Meta::Word(ref word)) if word == "readonly" => {
+                            options.readonly = true;
+                        },
+                        &NestedMeta::Meta(Meta::NameValue(ref name_value)) if name_value.ident == "rename" => {
+                            if let &Lit::Str(ref lit) = &name_value.lit {
+                                options.rename = Some(lit.value());
+                            } else {
+                                panic!("Invalid reflect attribute: Expected a string for rename value");
+                            }
+                        },
+                        _ => {
+                            panic!("Invalid reflect attribute: Expected skip, readonly or rename");
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    // fields that serde leaves out are not part of the serialized shape, so they have no path
+    options.skip |= serde_options(attrs).skip;
+    options
+}
+
+fn container_options(attrs: &[Attribute]) -> ContainerOptions {
+    let mut options = ContainerOptions::default();
+
+    for attr in attrs.iter() {
+        if let Some(Meta::List(list)) = attr.interpret_meta() {
+            if list.ident == "reflect" {
+                for nest_meta in list.nested.iter() {
+                    match nest_meta {
+                        &NestedMeta::Meta(Meta::NameValue(ref name_value)) if name_value.ident == "builder" => {
+                            if let &Lit::Str(ref lit) = &name_value.lit {
+                                options.builder = Some(lit.value());
+                            } else {
+                                panic!("Invalid reflect attribute: Expected a string for builder value");
+                            }
+                        },
+                        _ => {
+                            panic!("Invalid reflect attribute: Expected builder");
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    options
+}
+
+/// Returns the serialize half of a serde name, which is either `name = "..."` or
+///  `name(serialize = "...", deserialize = "...")`.
+fn serde_name(meta: &Meta) -> Option<String> {
+    match meta {
+        &Meta::NameValue(MetaNameValue { lit: Lit::Str(ref lit), .. }) => Some(lit.value()),
+        &Meta::List(ref list) => list.nested.iter().filter_map(|nest_meta| match nest_meta {
+            &NestedMeta::Meta(Meta::NameValue(ref name_value)) if name_value.ident == "serialize" => {
+                match &name_value.lit {
+                    &Lit::Str(ref lit) => Some(lit.value()),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }).next(),
+        _ => None,
+    }
+}
+
+fn serde_options(attrs: &[Attribute]) -> SerdeOptions {
+    let mut options = SerdeOptions::default();
+
+    for attr in attrs.iter() {
+        if let Some(Meta::List(list)) = attr.interpret_meta() {
+            if list.ident == "serde" {
+                for nest_meta in list.nested.iter() {
+                    if let &NestedMeta::Meta(ref meta) = nest_meta {
+                        match meta.name().to_string().as_ref() {
+                            "rename" => options.rename = serde_name(meta).or(options.rename),
+                            "rename_all" => options.rename_all = serde_name(meta).or(options.rename_all),
+                            "flatten" => options.flatten = true,
+                            "skip" | "skip_serializing" => options.skip = true,
+                            "tag" => options.tag = serde_name(meta).or(options.tag),
+                            "content" => options.content = serde_name(meta).or(options.content),
+                            "untagged" => options.untagged = true,
+                            _ => (),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    options
+}
+
+/// Converts a snake_case field name according to a serde `rename_all` rule.
+fn rename_field(field: &str, rule: &str) -> String {
+    match rule {
+        "lowercase" | "snake_case" => field.to_string(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => field.to_ascii_uppercase(),
+        "PascalCase" => {
+            let mut pascal = String::new();
+            let mut capitalize = true;
+            for ch in field.chars() {
+                if ch == '_' {
+                    capitalize = true;
+                } else if capitalize {
+                    pascal.push(ch.to_ascii_uppercase());
+                    capitalize = false;
+                } else {
+                    pascal.push(ch);
+                }
+            }
+            pascal
+        },
+        "camelCase" => {
+            let pascal = rename_field(field, "PascalCase");
+            pascal[..1].to_ascii_lowercase() + &pascal[1..]
+        },
+        "kebab-case" => field.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => field.to_ascii_uppercase().replace('_', "-"),
+        _ => panic!("Invalid serde attribute: Unknown rename_all rule {}", rule),
+    }
+}
+
+/// Converts a PascalCase variant name according to a serde `rename_all` rule.
+fn rename_variant(variant: &str, rule: &str) -> String {
+    match rule {
+        "lowercase" => variant.to_ascii_lowercase(),
+        "UPPERCASE" => variant.to_ascii_uppercase(),
+        "PascalCase" => variant.to_string(),
+        "camelCase" => variant[..1].to_ascii_lowercase() + &variant[1..],
+        "snake_case" => {
+            let mut snake = String::new();
+            for (i, ch) in variant.char_indices() {
+                if i > 0 && ch.is_uppercase() {
+                    snake.push('_');
+                }
+                snake.push(ch.to_ascii_lowercase());
+            }
+            snake
+        },
+        "SCREAMING_SNAKE_CASE" => rename_variant(variant, "snake_case").to_ascii_uppercase(),
+        "kebab-case" => rename_variant(variant, "snake_case").replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => rename_variant(variant, "SCREAMING_SNAKE_CASE").replace('_', "-"),
+        _ => panic!("Invalid serde attribute: Unknown rename_all rule {}", rule),
+    }
+}
+
+/// Returns the path element that leads to the fields of a variant, following the way serde tags
+///  the enum. Internally tagged and untagged enums keep the fields of a variant at the level of the
+///  enum itself, and adjacently tagged enums keep them in their content.
+fn variant_element(container: &SerdeOptions, variant: String) -> Option<String> {
+    match (&container.tag, &container.content) {
+        _ if container.untagged => None,
+        (&Some(_), &Some(ref content)) => Some(content.clone()),
+        (&Some(_), &None) => None,
+        (&None, _) => Some(variant),
+    }
+}
+
+/// Returns whether the only field of a variant is serialized in place of the variant, which is
+///  the case for newtype variants that have no element of their own.
+fn flattens_newtype(variant: &Variant, element: &Option<String>) -> bool {
+    match &variant.fields {
+        &Fields::Unnamed(ref fields) => element.is_none() && fields.unnamed.len() == 1,
+        _ => false,
+    }
+}
+
+/// Returns the path segment of a field, following the serialized name of the field unless it
+///  is overridden with `#[reflect(rename = "...")]`.
+fn field_name(field: &Field, index: usize, options: &FieldOptions, serde: &SerdeOptions, rename_all: &Option<String>) -> String {
+    if let Some(ref rename) = options.rename {
+        return rename.clone();
+    }
+    if let Some(ref rename) = serde.rename {
+        return rename.clone();
+    }
+    match (&field.ident, rename_all) {
+        (&Some(ref ident), &Some(ref rule)) => rename_field(&ident.to_string(), rule),
+        (&Some(ref ident), &None) => ident.to_string(),
+        (&None, _) => index.to_string(),
+    }
+}
+
+/// Returns whether `ty` mentions a type parameter of `generics`. Only those field types need a
+///  `Reflect` bound, and bounding the others would make recursive types like `Vec<Box<Self>>`
+///  require themselves.
+fn uses_type_param(ty: &Type, generics: &Generics) -> bool {
+    fn mentions(tokens: proc_macro2::TokenStream, params: &[&Ident]) -> bool {
+        tokens.into_iter().any(|token| match token {
+            proc_macro2::TokenTree::Ident(ref ident) => params.contains(&ident),
+            proc_macro2::TokenTree::Group(ref group) => mentions(group.stream(), params),
+            _ => false,
+        })
+    }
+
+    let params: Vec<&Ident> = generics.type_params().map(|param| &param.ident).collect();
+    mentions(quote! { #ty }, &params)
+}
+
+/// Fails for arrays of more than 32 elements, which serde can't serialize. They would otherwise
+///  fail with an error about `Reflect` not being implemented, which doesn't say why.
+fn check_array_len(ty: &Type) {
+    if let &Type::Array(ref array) = ty {
+        if let &Expr::Lit(ExprLit { lit: Lit::Int(ref len), .. }) = &array.len {
+            if len.value() > 32 {
+                panic!("Arrays of more than 32 elements can't be serialized, use mirror::Array<T, N> instead");
+            }
+        }
+    }
+}
+
+/// Builds the if-else chain that forwards a `Command::Path` to the field named by `element`.
+/// The chain expects `element` and `next` to be bound to the contents of the path command, which
+///  itself is passed in as the `path` expression. Flattened fields are tried in order when no field matches,
+///  and receive the full path command since their fields live at the same level.
+fn impl_path_chain(fields: &[ReflectField], path: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let mut arms: Vec<proc_macro2::TokenStream> = vec!();
+    let mut flattened: Vec<proc_macro2::TokenStream> = vec!();
+
+    for field in fields.iter() {
+        let name = &field.name;
+        let access = &field.access;
+
+        if field.flatten {
+            let forward = quote! {
+                context.with_inner("", |c| {
+                    result = #access.command(c, #path);
+                });
+            };
+
+            flattened.push(if field.readonly {
+                quote! {
+                    if let Err(Error::PathError) = result {
+                        if next.is_mutation() {
+                            result = Err(Error::ReadOnly);
+                        } else {
+                            #forward
+                        }
+                    }
+                }
+            } else {
+                quote! {
+                    if let Err(Error::PathError) = result {
+                        #forward
+                    }
+                }
+            });
+            continue;
+        }
+
+        let forward = quote! {
+            let mut result = Ok(());
+            context.with_inner(element.as_str(), |c| {
+                result = #access.command(c, next);
+            });
+            result
+        };
+
+        arms.push(if field.readonly {
+            quote! {
+                if element == #name {
+                    if next.is_mutation() {
+                        Err(Error::ReadOnly)
+                    } else {
+                        #forward
+                    }
+                }
+            }
+        } else {
+            quote! {
+                if element == #name {
+                    #forward
+                }
+            }
+        });
+    }
+
+    if flattened.is_empty() {
+        quote! {
+            #( #arms else )* {
+                Err(Error::PathError)
+            }
+        }
+    } else {
+        quote! {
+            #( #arms else )* {
+                let mut result = Err(Error::PathError);
+                #( #flattened )*
+                result
+            }
+        }
+    }
+}
+
+/// Builds the comparison of a field between the current and the new value for `check_readonly`.
+/// Read-only fields have to stay the same, other fields are checked for read-only fields of their own.
+fn impl_check_readonly(readonly: bool, old: proc_macro2::TokenStream, new: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if readonly {
+        quote! {
+            if serde_json::to_value(&#old)? != serde_json::to_value(&#new)? {
+                return Err(Error::ReadOnly);
+            }
+        }
+    } else {
+        quote! { Reflect::check_readonly(&#old, &#new)?; }
+    }
 }
 
 fn impl_reflect_struct(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
     let name = &ast.ident;
-    let mut field_id = Vec::<Member>::new();
+    let rename_all = serde_options(&ast.attrs).rename_all;
+    let mut fields = Vec::<ReflectField>::new();
     let mut field_ty = HashSet::new();
+    let mut diffs = Vec::new();
+    let mut checks = Vec::new();
+    let mut flattened = Vec::new();
 
     match &ast.data {
         &Data::Struct(ref data) => {
-            match &data.fields {
-                &Fields::Named(ref fields) => {
-                    for i in fields.named.iter() {
-                        field_id.push(Member::Named(i.ident.clone().unwrap()));
-                        field_ty.insert(i.ty.clone());
-                    }
-                },
-                &Fields::Unnamed(ref fields) => {
-                    for (i, f) in fields.unnamed.iter().enumerate() {
-                        field_id.push(Member::Unnamed(Index::from(i)));
-                        field_ty.insert(f.ty.clone());
+            for (i, f) in data.fields.iter().enumerate() {
+                let options = field_options(&f.attrs);
+                if options.skip {
+                    continue;
+                }
+                let serde = serde_options(&f.attrs);
+                check_array_len(&f.ty);
+
+                let member = match f.ident {
+                    Some(ref ident) => Member::Named(ident.clone()),
+                    None => Member::Unnamed(Index::from(i)),
+                };
+
+                let name = field_name(f, i, &options, &serde, &rename_all);
+                diffs.push(if options.readonly {
+                    // read-only fields can only be changed by setting the whole value
+                    quote! {
+                        if serde_json::to_value(&self.#member)? != serde_json::to_value(&new.#member)? {
+                            return diff_set(self, new);
+                        }
                     }
-                },
-                &Fields::Unit => {
-                    /* no fields */
-                },
+                } else if serde.flatten {
+                    quote! { commands.extend(Reflect::diff(&self.#member, &new.#member)?); }
+                } else {
+                    quote! { diff_child(#name, &self.#member, &new.#member, &mut commands)?; }
+                });
+
+                checks.push(impl_check_readonly(options.readonly, quote! { self.#member }, quote! { new.#member }));
+
+                if serde.flatten {
+                    let readonly = options.readonly;
+                    flattened.push(quote! { (serde_json::to_value(&this.#member)?, #readonly) });
+                }
+
+                fields.push(ReflectField {
+                    name,
+                    access: quote! { self.#member },
+                    readonly: options.readonly,
+                    flatten: serde.flatten,
+                });
+                if uses_type_param(&f.ty, &ast.generics) {
+                    field_ty.insert(f.ty.clone());
+                }
             }
         },
         _ => unreachable!(),
@@ -45,41 +435,81 @@ fn impl_reflect_struct(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
 
     let mut impl_generics: Generics = ast.generics.clone();
     //impl_generics.params.push(GenericParam::Lifetime(LifetimeDef::new(Lifetime::new("'de", Span::call_site()))));
-    impl_generics.where_clause = Some(parse_quote!(where #(#field_ty: Reflect,)*));
+    impl_generics.where_clause = Some(parse_quote!(where #(#field_ty: Reflect + serde::Serialize,)*));
 
     let (_, type_generics, _) = ast.generics.split_for_impl();
     let (impl_generics, _, where_clause) = impl_generics.split_for_impl();
 
-    let field_str: Vec<Member> = field_id.clone();
+    let path_chain = impl_path_chain(&fields, quote! { command });
+    let children = fields.iter().filter(|field| !field.flatten).map(|field| {
+        let name = &field.name;
+        let readonly = field.readonly;
+        quote! { (#name, #readonly) }
+    });
+
+    let each_arm = quote! {
+        &Command::Each { ref elements, ref command } => {
+            let mutation = command.is_mutation();
+            let children: &[(&str, bool)] = &[#(#children),*];
+            // flattened fields have their children at this level, under their serialized names
+            let children = |this: &Self| {
+                let flattened: Vec<(serde_json::Value, bool)> = vec![#(#flattened),*];
+                let mut names: Vec<String> = children.iter()
+                    .filter(|&&(_, readonly)| !(readonly && mutation))
+                    .map(|&(name, _)| name.to_string())
+                    .collect();
+                for (value, readonly) in flattened {
+                    if let serde_json::Value::Object(object) = value {
+                        if !(readonly && mutation) {
+                            names.extend(object.keys().cloned());
+                        }
+                    }
+                }
+                Ok(names)
+            };
+            apply_each(self, context, elements, children, command)
+        },
+    };
 
     let tokens = quote! {
         impl #impl_generics Reflect for #name #type_generics #where_clause {
             fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
                 use serde_json::from_value;
                 match command {
-                    &Command::Path { ref element, ref command } => {
-                        #(if element == stringify!(#field_str) {
-                            let mut result = Ok(());
-                            context.with_inner(element.as_str(), |c| {
-                                result = self.#field_id.command(c,command);
-                            });
-                            result
-                        } else )* {
-                            Err(Error::PathError)
-                        }
+                    &Command::Path { ref element, command: ref next } => {
+                        #path_chain
                     },
+                    #each_arm
                     &Command::Set { ref value } => {
-                        *self = from_value(value.clone())?;
+                        let value = from_value(value.clone())?;
+                        check_replace(self, &context, &value)?;
+                        *self = value;
                         Ok(())
                     },
                     &Command::Call { ref key, ref arguments } => {
-                        Ok(self._call(key.as_str(), context, arguments.as_slice())?)
+                        let response = self._call(key.as_str(), context.clone(), arguments.as_slice())?;
+                        context.respond(response);
+                        Ok(())
                     },
+                    &Command::Get => apply_get(self, context),
+                    &Command::Cas { ref expected, ref value } => apply_cas(self, &context, expected, value),
+                    &Command::Batch { ref commands } => apply_batch(self, context, commands),
                     &_ => {
                         Err(Error::IncompatibleCommand)
                     },
                 }
             }
+
+            fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+                let mut commands = Vec::new();
+                #(#diffs)*
+                Ok(commands)
+            }
+
+            fn check_readonly(&self, new: &Self) -> Result<(), Error> {
+                #(#checks)*
+                Ok(())
+            }
         }
     };
 
@@ -87,7 +517,170 @@ fn impl_reflect_struct(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
 }
 
 fn impl_reflect_enum(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
-    unimplemented!();
+    let name = &ast.ident;
+    let container = serde_options(&ast.attrs);
+    let rename_all = &container.rename_all;
+    let mut field_ty = HashSet::new();
+    let mut arms: Vec<proc_macro2::TokenStream> = vec!();
+    let mut check_arms: Vec<proc_macro2::TokenStream> = vec!();
+
+    let variants = match &ast.data {
+        &Data::Enum(ref data) => &data.variants,
+        _ => unreachable!(),
+    };
+
+    for variant in variants.iter() {
+        let variant_id = &variant.ident;
+        let variant_serde = serde_options(&variant.attrs);
+        let variant_str = match (variant_serde.rename, rename_all) {
+            (Some(rename), _) => rename,
+            (None, &Some(ref rule)) => rename_variant(&variant_id.to_string(), rule),
+            (None, &None) => variant_id.to_string(),
+        };
+        let element = variant_element(&container, variant_str);
+        let flatten_newtype = flattens_newtype(variant, &element);
+        let mut fields = Vec::<ReflectField>::new();
+        let mut bindings = Vec::<proc_macro2::TokenStream>::new();
+        let mut old_bindings = Vec::<proc_macro2::TokenStream>::new();
+        let mut new_bindings = Vec::<proc_macro2::TokenStream>::new();
+        let mut checks = Vec::<proc_macro2::TokenStream>::new();
+
+        for (i, f) in variant.fields.iter().enumerate() {
+            let options = field_options(&f.attrs);
+            let (binding, old_binding, new_binding) = if options.skip {
+                (quote! { _ }, quote! { _ }, quote! { _ })
+            } else {
+                let serde = serde_options(&f.attrs);
+                check_array_len(&f.ty);
+                let binding = Ident::new(&format!("__field{}", i), Span::call_site());
+                fields.push(ReflectField {
+                    name: field_name(f, i, &options, &serde, &variant_serde.rename_all),
+                    access: quote! { #binding },
+                    readonly: options.readonly,
+                    flatten: serde.flatten || flatten_newtype,
+                });
+                if uses_type_param(&f.ty, &ast.generics) {
+                    field_ty.insert(f.ty.clone());
+                }
+                let old = Ident::new(&format!("__old{}", i), Span::call_site());
+                let new = Ident::new(&format!("__new{}", i), Span::call_site());
+                checks.push(impl_check_readonly(options.readonly, quote! { *#old }, quote! { *#new }));
+                (quote! { ref mut #binding }, quote! { ref #old }, quote! { ref #new })
+            };
+
+            let member = |binding| match f.ident {
+                Some(ref ident) => quote! { #ident: #binding },
+                None => binding,
+            };
+            bindings.push(member(binding));
+            old_bindings.push(member(old_binding));
+            new_bindings.push(member(new_binding));
+        }
+
+        let (pattern, old_pattern, new_pattern) = match &variant.fields {
+            &Fields::Named(_) => (
+                quote! { #name::#variant_id { #(#bindings),* } },
+                quote! { #name::#variant_id { #(#old_bindings),* } },
+                quote! { #name::#variant_id { #(#new_bindings),* } },
+            ),
+            &Fields::Unnamed(_) => (
+                quote! { #name::#variant_id ( #(#bindings),* ) },
+                quote! { #name::#variant_id ( #(#old_bindings),* ) },
+                quote! { #name::#variant_id ( #(#new_bindings),* ) },
+            ),
+            &Fields::Unit => {
+                if let Some(element) = element {
+                    arms.push(quote! {
+                        #name::#variant_id if element == #element => Err(Error::PathError),
+                    });
+                }
+                continue;
+            },
+        };
+
+        // changing the variant is allowed, like setting the enum through its path
+        check_arms.push(quote! {
+            (&#old_pattern, &#new_pattern) => {
+                #(#checks)*
+            },
+        });
+
+        arms.push(match element {
+            Some(element) => {
+                let path_chain = impl_path_chain(&fields, quote! { &**next });
+                quote! {
+                    #pattern if element == #element => {
+                        let mut result = Ok(());
+                        context.with_inner(element.as_str(), |mut context| {
+                            result = match **next {
+                                Command::Path { ref element, command: ref next } => {
+                                    #path_chain
+                                },
+                                _ => Err(Error::IncompatibleCommand),
+                            };
+                        });
+                        result
+                    },
+                }
+            },
+            None => {
+                let path_chain = impl_path_chain(&fields, quote! { command });
+                quote! {
+                    #pattern => {
+                        #path_chain
+                    },
+                }
+            },
+        });
+    }
+
+    let mut impl_generics: Generics = ast.generics.clone();
+    impl_generics.where_clause = Some(parse_quote!(where #(#field_ty: Reflect + serde::Serialize,)*));
+
+    let (_, type_generics, _) = ast.generics.split_for_impl();
+    let (impl_generics, _, where_clause) = impl_generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics Reflect for #name #type_generics #where_clause {
+            fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
+                use serde_json::from_value;
+                match command {
+                    &Command::Path { ref element, command: ref next } => {
+                        match *self {
+                            #( #arms )*
+                            _ => Err(Error::PathError),
+                        }
+                    },
+                    &Command::Set { ref value } => {
+                        let value = from_value(value.clone())?;
+                        check_replace(self, &context, &value)?;
+                        *self = value;
+                        Ok(())
+                    },
+                    &Command::Call { ref key, ref arguments } => {
+                        let response = self._call(key.as_str(), context.clone(), arguments.as_slice())?;
+                        context.respond(response);
+                        Ok(())
+                    },
+                    &Command::Get => apply_get(self, context),
+                    &Command::Cas { ref expected, ref value } => apply_cas(self, &context, expected, value),
+                    &Command::Batch { ref commands } => apply_batch(self, context, commands),
+                    &_ => {
+                        Err(Error::IncompatibleCommand)
+                    },
+                }
+            }
+
+            fn check_readonly(&self, new: &Self) -> Result<(), Error> {
+                #[allow(unreachable_patterns)]
+                match (self, new) {
+                    #( #check_arms )*
+                    _ => (),
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 fn attr_to_action(attr: &Meta) -> MirroredAction {
@@ -95,6 +688,7 @@ fn attr_to_action(attr: &Meta) -> MirroredAction {
         if list.ident == "Fn" {
             let mut function: Option<LitStr> = None;
             let mut args: usize = 0;
+            let mut result = false;
             for nest_meta in list.nested.iter() {
                 if let &NestedMeta::Meta (ref meta) = nest_meta {
                     match meta {
@@ -117,6 +711,13 @@ fn attr_to_action(attr: &Meta) -> MirroredAction {
                                         panic!("Invalid Fn attribute: Expected a string for args value");
                                     }
                                 }
+                                "result" => {
+                                    if let &Lit::Str(ref lit) = &name_value.lit {
+                                        result = lit.value().parse::<bool>().expect("Invalid Fn attribute: Expected a string that can parse into bool");
+                                    } else {
+                                        panic!("Invalid Fn attribute: Expected a string for result value");
+                                    }
+                                }
                                 _ => {
                                     panic!("Invalid Fn attribute: Invalid value in list");
                                 }
@@ -137,6 +738,7 @@ fn attr_to_action(attr: &Meta) -> MirroredAction {
             MirroredAction {
                 function,
                 args,
+                result,
             }
         } else {
             panic!("Invalid Fn attribute: Needs to be a list")
@@ -146,6 +748,23 @@ fn attr_to_action(attr: &Meta) -> MirroredAction {
     }
 }
 
+/// Converts the return value of an exported function to the result of `_call`.
+/// Functions that return a `Result` fail the command with their error message on `Err`.
+fn impl_response(call: proc_macro2::TokenStream, result: bool) -> proc_macro2::TokenStream {
+    if result {
+        quote! {
+            match #call {
+                Ok(value) => Ok(serde_json::to_value(value)?),
+                Err(error) => Err(Error::FunctionError(error.to_string())),
+            }
+        }
+    } else {
+        quote! {
+            Ok(serde_json::to_value(#call)?)
+        }
+    }
+}
+
 fn impl_reflect_actions(ast: &DeriveInput) -> proc_macro2::TokenStream {
     let name = &ast.ident;
 
@@ -170,10 +789,17 @@ fn impl_reflect_actions(ast: &DeriveInput) -> proc_macro2::TokenStream {
         }
     }
 
+    // Without exported functions, `_call` resolves to the `NoFunctions` fallback or to the
+    //  function generated by `#[reflect_methods]`.
+    if actions.is_empty() {
+        return quote! {};
+    }
+
     let mut arms: Vec<proc_macro2::TokenStream> = vec!();
 
     for action in actions {
         let action_name = &action.function;
+        let arg_count = action.args;
         let span = action.function.span();
         let function_name = Ident::new(action.function.value().as_ref(), span);
 
@@ -187,11 +813,14 @@ fn impl_reflect_actions(ast: &DeriveInput) -> proc_macro2::TokenStream {
         let function_call = quote_spanned!{ span =>
             #function_name(context, #( #args ),*)
         };
+        let response = impl_response(quote! { self.#function_call }, action.result);
 
         arms.push(quote_spanned!{ Span::call_site() =>
             #action_name => {
-                self.#function_call;
-                Ok(())
+                if arguments.len() != #arg_count {
+                    return Err(Error::WrongArgumentCount);
+                }
+                #response
             }
         });
     }
@@ -205,7 +834,7 @@ fn impl_reflect_actions(ast: &DeriveInput) -> proc_macro2::TokenStream {
 
     let tokens = quote! {
         impl #impl_generics #name #type_generics #where_clause {
-            fn _call<C: Context>(&mut self, key: &str, context: C, arguments: &[serde_json::Value]) -> Result<(), Error> {
+            fn _call<C: Context>(&mut self, key: &str, context: C, arguments: &[serde_json::Value]) -> Result<serde_json::Value, Error> {
                 #match_statement
             }
         }
@@ -214,6 +843,280 @@ fn impl_reflect_actions(ast: &DeriveInput) -> proc_macro2::TokenStream {
     tokens
 }
 
+/// Returns whether `ty` is a `Context`, either as `impl Context` or as a type parameter of the
+///  method that is bound by `Context`.
+fn is_context(ty: &Type, generics: &Generics) -> bool {
+    fn has_context_bound<'a, I: IntoIterator<Item = &'a TypeParamBound>>(bounds: I) -> bool {
+        bounds.into_iter().any(|bound| match bound {
+            &TypeParamBound::Trait(ref bound) => bound.path.segments.last()
+                .map(|segment| segment.value().ident == "Context")
+                .unwrap_or(false),
+            _ => false,
+        })
+    }
+
+    match ty {
+        &Type::ImplTrait(ref impl_trait) => has_context_bound(impl_trait.bounds.iter()),
+        &Type::Path(TypePath { qself: None, ref path }) if path.segments.len() == 1 => {
+            let ident = &path.segments[0].ident;
+            let in_params = generics.type_params()
+                .filter(|param| &param.ident == ident)
+                .any(|param| has_context_bound(param.bounds.iter()));
+            let in_where = generics.where_clause.iter()
+                .flat_map(|where_clause| where_clause.predicates.iter())
+                .any(|predicate| match predicate {
+                    &WherePredicate::Type(ref predicate) => {
+                        predicate.bounded_ty == *ty && has_context_bound(predicate.bounds.iter())
+                    },
+                    _ => false,
+                });
+            in_params || in_where
+        },
+        _ => false,
+    }
+}
+
+/// Returns whether a method returns a `Result`, judging by the name of the return type.
+fn returns_result(method: &ImplItemMethod) -> bool {
+    match &method.sig.decl.output {
+        &ReturnType::Type(_, ref ty) => match **ty {
+            Type::Path(ref path) => path.path.segments.last()
+                .map(|segment| segment.value().ident == "Result")
+                .unwrap_or(false),
+            _ => false,
+        },
+        &ReturnType::Default => false,
+    }
+}
+
+/// Returns the json argument types of a method that can be exported to `Command::Call`.
+/// Exportable methods take `&self` or `&mut self`, followed by a `Context` and zero or more
+///  arguments that can be deserialized.
+fn method_arguments(method: &ImplItemMethod) -> Option<Vec<Type>> {
+    let mut inputs = method.sig.decl.inputs.iter();
+
+    match inputs.next() {
+        Some(&FnArg::SelfRef(_)) => (),
+        _ => return None,
+    }
+    match inputs.next() {
+        Some(&FnArg::Captured(ref arg)) if is_context(&arg.ty, &method.sig.decl.generics) => (),
+        _ => return None,
+    }
+
+    inputs.map(|input| match input {
+        &FnArg::Captured(ref arg) => Some(arg.ty.clone()),
+        &FnArg::Ignored(ref ty) => Some(ty.clone()),
+        _ => None,
+    }).collect()
+}
+
+fn impl_reflect_methods(item: &mut ItemImpl) -> proc_macro2::TokenStream {
+    let mut methods = Vec::new();
+
+    for impl_item in item.items.iter_mut() {
+        if let &mut ImplItem::Method(ref mut method) = impl_item {
+            let attr_count = method.attrs.len();
+            method.attrs.retain(|attr| !attr.path.is_ident("reflect_fn"));
+            let marked = method.attrs.len() != attr_count;
+
+            let arguments = method_arguments(method);
+            if marked && arguments.is_none() {
+                panic!("Invalid reflect_fn attribute: {} needs to take self by reference and a Context",
+                       method.sig.ident);
+            }
+            if let Some(arguments) = arguments {
+                methods.push((marked, method.sig.ident.clone(), arguments, returns_result(method)));
+            }
+        }
+    }
+
+    // when some methods are marked with #[reflect_fn], only those are exported.
+    if methods.iter().any(|&(marked, _, _, _)| marked) {
+        methods.retain(|&(marked, _, _, _)| marked);
+    }
+
+    let mut arms: Vec<proc_macro2::TokenStream> = vec!();
+
+    for (_, ident, arguments, result) in methods {
+        let name = ident.to_string();
+        let arg_count = arguments.len();
+        let index = 0..arg_count;
+        let response = impl_response(quote! {
+            self.#ident(context, #( serde_json::from_value::<#arguments>(arguments[#index].clone())? ),*)
+        }, result);
+
+        arms.push(quote! {
+            #name => {
+                if arguments.len() != #arg_count {
+                    return Err(Error::WrongArgumentCount);
+                }
+                #response
+            }
+        });
+    }
+
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics #self_ty #where_clause {
+            fn _call<C: Context>(&mut self, key: &str, context: C, arguments: &[serde_json::Value]) -> Result<serde_json::Value, Error> {
+                match key {
+                    #( #arms )*
+                    _ => Err(Error::InvalidCommand)
+                }
+            }
+        }
+    }
+}
+
+/// Builds the functions of a command builder that traverse to the fields of a struct or variant.
+/// Skipped and read only fields can't be changed, so they get no function.
+fn impl_builder_fields(fields: &Fields, rename_all: &Option<String>, flatten_newtype: bool, vis: &Visibility) -> Vec<proc_macro2::TokenStream> {
+    let mut functions = Vec::new();
+
+    for (i, f) in fields.iter().enumerate() {
+        let options = field_options(&f.attrs);
+        if options.skip || options.readonly {
+            continue;
+        }
+        let serde = serde_options(&f.attrs);
+        let name = field_name(f, i, &options, &serde, rename_all);
+        let ty = &f.ty;
+        let function = match f.ident {
+            Some(ref ident) => ident.clone(),
+            None => Ident::new(&format!("_{}", i), Span::call_site()),
+        };
+
+        // the bound is on a generic builder type, so fields that have no builder only fail
+        //  when their function is used.
+        let child = if serde.flatten || flatten_newtype {
+            quote! { self.0.flatten::<#ty>().into() }
+        } else {
+            quote! { self.0.child::<#ty>(#name).into() }
+        };
+        functions.push(quote! {
+            #vis fn #function<B>(self) -> B where #ty: BuildCommand<Builder = B>, B: From<PathBuilder<#ty>> {
+                #child
+            }
+        });
+    }
+
+    functions
+}
+
+/// Creates the identifier of a generated function, with a trailing `_` if the name is a keyword.
+fn function_ident(name: &str) -> Ident {
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+        "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+        "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+        "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+        "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+
+    if KEYWORDS.contains(&name) {
+        Ident::new(&format!("{}_", name), Span::call_site())
+    } else {
+        Ident::new(name, Span::call_site())
+    }
+}
+
+fn impl_command_builder(ast: &DeriveInput) -> proc_macro2::TokenStream {
+    let name = &ast.ident;
+    let vis = &ast.vis;
+    let container = serde_options(&ast.attrs);
+    let rename_all = &container.rename_all;
+    let builder_name = container_options(&ast.attrs).builder;
+    let builder = match &builder_name {
+        &Some(ref builder) => Ident::new(builder, Span::call_site()),
+        &None => Ident::new(&format!("{}Cmd", name), Span::call_site()),
+    };
+
+    let (impl_generics, type_generics, where_clause) = ast.generics.split_for_impl();
+
+    let mut functions = Vec::new();
+    let mut variant_builders = Vec::new();
+
+    match &ast.data {
+        &Data::Struct(ref data) => {
+            functions = impl_builder_fields(&data.fields, rename_all, false, vis);
+        },
+        &Data::Enum(ref data) => {
+            for variant in data.variants.iter() {
+                if let Fields::Unit = variant.fields {
+                    continue;
+                }
+
+                let variant_id = &variant.ident;
+                let variant_serde = serde_options(&variant.attrs);
+                let variant_str = match (variant_serde.rename, rename_all) {
+                    (Some(rename), _) => rename,
+                    (None, &Some(ref rule)) => rename_variant(&variant_id.to_string(), rule),
+                    (None, &None) => variant_id.to_string(),
+                };
+                let element = variant_element(&container, variant_str);
+                let flatten_newtype = flattens_newtype(variant, &element);
+                let function = function_ident(&rename_variant(&variant_id.to_string(), "snake_case"));
+                let variant_builder = match &builder_name {
+                    &Some(ref builder) => Ident::new(&format!("{}{}", builder, variant_id), Span::call_site()),
+                    &None => Ident::new(&format!("{}{}Cmd", name, variant_id), Span::call_site()),
+                };
+                let variant_functions = impl_builder_fields(&variant.fields, &variant_serde.rename_all, flatten_newtype, vis);
+                let path = match element {
+                    Some(element) => quote! { self.0.child::<#name #type_generics>(#element) },
+                    None => quote! { self.0.flatten::<#name #type_generics>() },
+                };
+
+                functions.push(quote! {
+                    #vis fn #function(self) -> #variant_builder #type_generics {
+                        #variant_builder(#path)
+                    }
+                });
+                variant_builders.push(quote! {
+                    /// Builds commands on the fields of a variant. Generated by `#[derive(Reflect)]`.
+                    #vis struct #variant_builder #impl_generics (PathBuilder<#name #type_generics>) #where_clause;
+
+                    impl #impl_generics #variant_builder #type_generics #where_clause {
+                        #( #variant_functions )*
+                    }
+                });
+            }
+        },
+        &Data::Union(_) => unreachable!(),
+    }
+
+    quote! {
+        /// Builds commands on a path. Generated by `#[derive(Reflect)]`.
+        #vis struct #builder #impl_generics (PathBuilder<#name #type_generics>) #where_clause;
+
+        impl #impl_generics From<PathBuilder<#name #type_generics>> for #builder #type_generics #where_clause {
+            fn from(path: PathBuilder<#name #type_generics>) -> Self {
+                #builder(path)
+            }
+        }
+
+        impl #impl_generics ::std::ops::Deref for #builder #type_generics #where_clause {
+            type Target = PathBuilder<#name #type_generics>;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl #impl_generics #builder #type_generics #where_clause {
+            #( #functions )*
+        }
+
+        impl #impl_generics BuildCommand for #name #type_generics #where_clause {
+            type Builder = #builder #type_generics;
+        }
+
+        #( #variant_builders )*
+    }
+}
+
 fn reflect(input: &DeriveInput) -> TokenStream {
     let reflect_impl = match &input.data {
         &Data::Struct(_) => {
@@ -228,18 +1131,46 @@ fn reflect(input: &DeriveInput) -> TokenStream {
     };
 
     let actions_impl = impl_reflect_actions(input);
+    let builder_impl = impl_command_builder(input);
 
     let tokens = quote! {
         #reflect_impl
         #actions_impl
+        #builder_impl
     };
 
     tokens.into()
 }
 
-#[proc_macro_derive(Reflect, attributes(ReflectFn))]
+/// Derives `Reflect` and a typed command builder, with paths that follow the serialized shape.
+/// The type has to implement `Serialize` as well, for commands like `batch:` and `get:`.
+/// Serde's `rename`, `rename_all`, `flatten`, `skip`, `skip_serializing`, `tag`, `content` and
+///  `untagged` are honoured, so fields that serde skips have no path, and the variants of
+///  internally tagged and untagged enums add no element to the paths of their fields.
+/// Fields take `#[reflect(skip)]`, `#[reflect(readonly)]` and `#[reflect(rename = "...")]`.
+/// Read-only fields reject every mutation through their path, and remotes can't change them by
+///  replacing a value they are in. The type takes `#[reflect(builder = "...")]` to name its builder.
+#[proc_macro_derive(Reflect, attributes(ReflectFn, reflect))]
 pub fn derive_reflect(input: TokenStream) -> proc_macro::TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as DeriveInput);
     reflect(&input)
-}
\ No newline at end of file
+}
+
+/// Exports the methods of an impl block to `Command::Call` on a type that derives `Reflect`.
+/// Methods that take `&self` or `&mut self` followed by a `Context` are exported, unless some
+///  methods are marked with `#[reflect_fn]`. In that case only the marked methods are exported.
+/// Methods that return a `Result` fail the command with `Error::FunctionError` on `Err`.
+/// The attribute can be used on only one impl block per type.
+#[proc_macro_attribute]
+pub fn reflect_methods(_attr: TokenStream, input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as ItemImpl);
+    let call_impl = impl_reflect_methods(&mut input);
+
+    let tokens = quote! {
+        #input
+        #call_impl
+    };
+
+    tokens.into()
+}
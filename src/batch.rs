@@ -0,0 +1,56 @@
+use super::*;
+use serde_json::{from_value, to_value};
+
+/// Execute `commands` on `value` as a single unit, used by `Reflect` implementations to handle
+///  `Command::Batch`.
+/// The commands run in order within `Context::batch`. If one of them fails, the changes are
+///  undone and the error is returned. To undo them, `value` is compared to a copy of itself from
+///  before the first command, and only the parts that differ are set back, as computed by
+///  `Reflect::diff`. Parts that are not serialized, like `Hidden` values and fields with
+///  `#[serde(skip)]`, are kept, unless a part they are in is replaced as a whole.
+pub fn apply_batch<R: Reflect + Serialize, C: Context>(value: &mut R, context: C, commands: &[Command]) -> Result<(), Error> {
+    execute_batch(value, context, commands, |value, context, command| value.command(context, command))
+}
+
+/// Same as `apply_batch`, but executes every command with `execute`.
+pub(crate) fn execute_batch<R, C, F>(value: &mut R, mut context: C, commands: &[Command], mut execute: F) -> Result<(), Error> where
+    R: Reflect + Serialize,
+    C: Context,
+    F: FnMut(&mut R, C, &Command) -> Result<(), Error>
+{
+    let snapshot = to_value(&*value)?;
+    let result = context.batch(|context| {
+        for command in commands {
+            execute(value, context.clone(), command)?;
+        }
+        Ok(())
+    });
+
+    if result.is_err() {
+        let old: R = from_value(snapshot)?;
+        for command in value.diff(&old)? {
+            value.command((), &command)?;
+        }
+    }
+
+    result
+}
+
+/// Execute `command` on several children of `value` as a single unit, used by `Reflect`
+///  implementations to handle `Command::Each`.
+/// `children` lists the names of all children, for when `elements` is `None`.
+pub fn apply_each<R, C, F>(value: &mut R, context: C, elements: &Option<Vec<String>>, children: F, command: &Command) -> Result<(), Error> where
+    R: Reflect + Serialize,
+    C: Context,
+    F: FnOnce(&R) -> Result<Vec<String>, Error>
+{
+    let elements = match elements {
+        Some(elements) => elements.clone(),
+        None => children(value)?,
+    };
+
+    let commands: Vec<Command> = elements.into_iter()
+        .map(|element| Command::Path { element, command: Box::new(command.clone()) })
+        .collect();
+    apply_batch(value, context, &commands)
+}
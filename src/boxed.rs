@@ -0,0 +1,16 @@
+use super::*;
+
+/// Boxes are transparent: commands and paths go straight to the boxed value.
+impl<T: Reflect + Serialize> Reflect for Box<T> {
+    fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+        (**self).command(context, command)
+    }
+
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+        (**self).diff(new)
+    }
+
+    fn check_readonly(&self, new: &Self) -> Result<(), Error> {
+        (**self).check_readonly(new)
+    }
+}
@@ -0,0 +1,361 @@
+use super::*;
+use serde_json::to_value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::marker::PhantomData;
+
+/// Types that have a typed builder for commands, starting with `T::cmd()`.
+/// Deriving `Reflect` generates a builder named `{Type}Cmd`, with a function for every field.
+/// The name can be changed with `#[reflect(builder = "...")]` on the type, and functions whose
+///  name is a keyword get a trailing `_`, like `type_`.
+/// The builders of container types are `PathBuilder` with functions like `at`.
+///
+/// Functions that serialize a value return a `Result`, since not every value has a json form.
+///
+/// Example: `FooBar::cmd().foo().bar().at(1).set(&5)?` builds `foo/bar/1/set:5`.
+pub trait BuildCommand: Sized {
+    type Builder: From<PathBuilder<Self>>;
+
+    /// Start building a command on a value of this type.
+    fn cmd() -> Self::Builder {
+        PathBuilder::root().into()
+    }
+}
+
+/// Builds commands on the element of type `T` at a path.
+pub struct PathBuilder<T> {
+    path: Vec<String>,
+    ph: PhantomData<T>,
+}
+
+impl<T> PathBuilder<T> {
+    /// A builder for commands on the root value.
+    pub fn root() -> Self {
+        PathBuilder { path: Vec::new(), ph: PhantomData }
+    }
+
+    /// Traverse to the child element of type `U` named `element`.
+    pub fn child<U>(mut self, element: &str) -> PathBuilder<U> {
+        self.path.push(element.to_string());
+        PathBuilder { path: self.path, ph: PhantomData }
+    }
+
+    /// Reinterpret the element as a `U` without traversing, for flattened fields.
+    pub fn flatten<U>(self) -> PathBuilder<U> {
+        PathBuilder { path: self.path, ph: PhantomData }
+    }
+
+    /// Wrap `command` in the path to the element.
+    pub fn build(&self, command: Command) -> Command {
+        self.path.iter().rev().fold(command, |command, element| Command::Path {
+            element: element.clone(),
+            command: Box::new(command),
+        })
+    }
+
+    /// Call the function `key` on the element.
+    pub fn call(&self, key: &str, arguments: Vec<Value>) -> Command {
+        self.build(Command::Call { key: key.to_string(), arguments })
+    }
+
+    /// Query the serialized value of the element.
+    pub fn get(&self) -> Command {
+        self.build(Command::Get)
+    }
+
+    /// Execute `commands` on the element as a single unit. Their paths start at the element.
+    pub fn batch(&self, commands: Vec<Command>) -> Command {
+        self.build(Command::Batch { commands })
+    }
+}
+
+impl<T: Serialize> PathBuilder<T> {
+    /// Overwrite the element with `value`.
+    pub fn set(&self, value: &T) -> Result<Command, Error> {
+        Ok(self.build(Command::Set { value: json(value)? }))
+    }
+
+    /// Overwrite the element with `value`, but only if it is still equal to `expected`.
+    pub fn cas(&self, expected: &T, value: &T) -> Result<Command, Error> {
+        Ok(self.build(Command::Cas { expected: json(expected)?, value: json(value)? }))
+    }
+}
+
+impl<T> Clone for PathBuilder<T> {
+    fn clone(&self) -> Self {
+        PathBuilder { path: self.path.clone(), ph: PhantomData }
+    }
+}
+
+/// Serializes a value for a command, which fails for values that have no json representation.
+fn json<T: Serialize>(value: &T) -> Result<Value, Error> {
+    Ok(to_value(value)?)
+}
+
+macro_rules! list {
+    ($($list:ident),*) => { $(
+        impl<T: BuildCommand + Serialize> PathBuilder<$list<T>> {
+            pub fn at(self, index: usize) -> T::Builder {
+                self.child::<T>(&index.to_string()).into()
+            }
+
+            pub fn push(&self, value: &T) -> Result<Command, Error> {
+                Ok(self.build(Command::Push { value: json(value)? }))
+            }
+
+            pub fn pop(&self) -> Command {
+                self.build(Command::Pop)
+            }
+
+            pub fn remove(&self, index: usize) -> Command {
+                self.build(Command::Remove { key: Value::from(index) })
+            }
+
+            pub fn insert(&self, index: usize, value: &T) -> Result<Command, Error> {
+                Ok(self.build(Command::Insert { key: Value::from(index), value: json(value)? }))
+            }
+
+            pub fn swap(&self, a: usize, b: usize) -> Command {
+                self.build(Command::Swap { a, b })
+            }
+
+            pub fn move_element(&self, from: usize, to: usize) -> Command {
+                self.build(Command::Move { from, to })
+            }
+
+            pub fn truncate(&self, len: usize) -> Command {
+                self.build(Command::Truncate { len })
+            }
+
+            pub fn clear(&self) -> Command {
+                self.build(Command::Clear)
+            }
+
+            pub fn extend(&self, values: &[T]) -> Result<Command, Error> {
+                Ok(self.build(Command::Extend { value: json(&values)? }))
+            }
+        }
+
+        impl<T> BuildCommand for $list<T> {
+            type Builder = PathBuilder<$list<T>>;
+        }
+    )* };
+}
+
+list!(Vec, VecDeque);
+
+macro_rules! fixed {
+    ($($list:ty),*) => { $(
+        impl<T: BuildCommand, const N: usize> PathBuilder<$list> {
+            pub fn at(self, index: usize) -> T::Builder {
+                self.child::<T>(&index.to_string()).into()
+            }
+
+            pub fn swap(&self, a: usize, b: usize) -> Command {
+                self.build(Command::Swap { a, b })
+            }
+
+            pub fn move_element(&self, from: usize, to: usize) -> Command {
+                self.build(Command::Move { from, to })
+            }
+        }
+
+        impl<T, const N: usize> BuildCommand for $list {
+            type Builder = PathBuilder<$list>;
+        }
+    )* };
+}
+
+fixed!([T; N], Array<T, N>);
+
+macro_rules! map {
+    ($($map:ident),*) => { $(
+        impl<K: Serialize, V: BuildCommand + Serialize> PathBuilder<$map<K, V>> {
+            pub fn at(self, key: &K) -> Result<V::Builder, Error> {
+                Ok(self.child::<V>(&json(key)?.to_string()).into())
+            }
+
+            pub fn insert(&self, key: &K, value: &V) -> Result<Command, Error> {
+                Ok(self.build(Command::Insert { key: json(key)?, value: json(value)? }))
+            }
+
+            pub fn remove(&self, key: &K) -> Result<Command, Error> {
+                Ok(self.build(Command::Remove { key: json(key)? }))
+            }
+        }
+
+        impl<K, V> BuildCommand for $map<K, V> {
+            type Builder = PathBuilder<$map<K, V>>;
+        }
+    )* };
+}
+
+map!(HashMap, BTreeMap);
+
+macro_rules! set {
+    ($($set:ident),*) => { $(
+        impl<T: Serialize> PathBuilder<$set<T>> {
+            pub fn insert(&self, value: &T) -> Result<Command, Error> {
+                Ok(self.build(Command::Insert { key: json(value)?, value: Value::Null }))
+            }
+
+            pub fn remove(&self, value: &T) -> Result<Command, Error> {
+                Ok(self.build(Command::Remove { key: json(value)? }))
+            }
+
+            pub fn clear(&self) -> Command {
+                self.build(Command::Clear)
+            }
+
+            pub fn extend(&self, values: &[T]) -> Result<Command, Error> {
+                Ok(self.build(Command::Extend { value: json(&values)? }))
+            }
+        }
+
+        impl<T> BuildCommand for $set<T> {
+            type Builder = PathBuilder<$set<T>>;
+        }
+    )* };
+}
+
+set!(HashSet, BTreeSet);
+
+impl<T: BuildCommand> PathBuilder<Option<T>> {
+    pub fn val(self) -> T::Builder {
+        self.child::<T>("val").into()
+    }
+
+    /// Set the option to `None`.
+    pub fn remove(&self) -> Command {
+        self.build(Command::Remove { key: Value::Null })
+    }
+}
+
+impl<T> BuildCommand for Option<T> {
+    type Builder = PathBuilder<Option<T>>;
+}
+
+/// Tuples have a function for every position, named like those of unnamed struct fields.
+macro_rules! tuple {
+    ($($p:ident $i:tt $function:ident),*) => {
+        impl<$($p: BuildCommand),*> PathBuilder<($($p,)*)> {
+            $(
+                pub fn $function(self) -> $p::Builder {
+                    self.child::<$p>(stringify!($i)).into()
+                }
+            )*
+        }
+
+        impl<$($p),*> BuildCommand for ($($p,)*) {
+            type Builder = PathBuilder<($($p,)*)>;
+        }
+    };
+}
+
+tuple!(T0 0 _0);
+tuple!(T0 0 _0, T1 1 _1);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8, T9 9 _9);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8, T9 9 _9, T10 10 _10);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8, T9 9 _9, T10 10 _10, T11 11 _11);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8, T9 9 _9, T10 10 _10, T11 11 _11, T12 12 _12);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8, T9 9 _9, T10 10 _10, T11 11 _11, T12 12 _12, T13 13 _13);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8, T9 9 _9, T10 10 _10, T11 11 _11, T12 12 _12, T13 13 _13, T14 14 _14);
+tuple!(T0 0 _0, T1 1 _1, T2 2 _2, T3 3 _3, T4 4 _4, T5 5 _5, T6 6 _6, T7 7 _7, T8 8 _8, T9 9 _9, T10 10 _10, T11 11 _11, T12 12 _12, T13 13 _13, T14 14 _14, T15 15 _15);
+
+impl<T: Numeric> PathBuilder<T> {
+    /// Add `delta` to the number. Negative deltas also work on unsigned integers.
+    pub fn add(&self, delta: T::Delta) -> Result<Command, Error> {
+        Ok(self.build(Command::Add { value: json(&delta)? }))
+    }
+
+    /// Multiply the number by `factor`.
+    pub fn mul(&self, factor: T::Delta) -> Result<Command, Error> {
+        Ok(self.build(Command::Mul { value: json(&factor)? }))
+    }
+
+    /// Keep the smallest of the number and `value`.
+    pub fn min(&self, value: &T) -> Result<Command, Error> {
+        Ok(self.build(Command::Min { value: json(value)? }))
+    }
+
+    /// Keep the largest of the number and `value`.
+    pub fn max(&self, value: &T) -> Result<Command, Error> {
+        Ok(self.build(Command::Max { value: json(value)? }))
+    }
+}
+
+impl PathBuilder<String> {
+    /// Replace `delete` chars starting at the char `offset` with `insert`.
+    pub fn splice(&self, offset: usize, delete: usize, insert: &str) -> Command {
+        self.build(Command::Splice { offset, delete, insert: insert.to_string() })
+    }
+}
+
+impl PathBuilder<Value> {
+    /// Traverse to the member `key` of an object.
+    pub fn key(self, key: &str) -> PathBuilder<Value> {
+        self.child::<Value>(&Value::from(key).to_string())
+    }
+
+    /// Traverse to the element at `index` of an array.
+    pub fn at(self, index: usize) -> PathBuilder<Value> {
+        self.child::<Value>(&index.to_string())
+    }
+
+    pub fn push(&self, value: Value) -> Command {
+        self.build(Command::Push { value })
+    }
+
+    pub fn pop(&self) -> Command {
+        self.build(Command::Pop)
+    }
+
+    /// Insert `value` at an index of an array, or at a key of an object.
+    pub fn insert<K: Into<Value>>(&self, key: K, value: Value) -> Command {
+        self.build(Command::Insert { key: key.into(), value })
+    }
+
+    /// Remove the element at an index of an array, or at a key of an object.
+    pub fn remove<K: Into<Value>>(&self, key: K) -> Command {
+        self.build(Command::Remove { key: key.into() })
+    }
+}
+
+impl BuildCommand for Value {
+    type Builder = PathBuilder<Value>;
+}
+
+macro_rules! primitive {
+    ($($p:ty),*) => { $(
+        impl BuildCommand for $p {
+            type Builder = PathBuilder<$p>;
+        }
+    )* };
+}
+
+primitive!(bool, char, (), i8, u8, i16, u16, i32, u32, i64, u64, i128, u128, isize, usize, f32, f64, String);
+
+/// Boxes are transparent, so `inner` continues with the builder of the boxed value.
+impl<T> BuildCommand for Box<T> {
+    type Builder = PathBuilder<Box<T>>;
+}
+
+impl<T: BuildCommand> PathBuilder<Box<T>> {
+    pub fn inner(self) -> T::Builder {
+        self.flatten::<T>().into()
+    }
+}
+
+impl<T> BuildCommand for Hidden<T> {
+    type Builder = PathBuilder<Hidden<T>>;
+}
+
+impl BuildCommand for Box<dyn DynReflect> {
+    type Builder = PathBuilder<Box<dyn DynReflect>>;
+}
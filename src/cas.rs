@@ -0,0 +1,38 @@
+use super::*;
+use serde_json::{from_value, to_value};
+
+/// Overwrite `value` with `new`, but only if it currently serializes equal to `expected`.
+/// Used by `Reflect` implementations to handle `Command::Cas`.
+/// `expected` is deserialized into the type of `value` before comparing, so that equal values
+///  with different json representations, like `1` and `1.0` for floats, match.
+/// Fails with `Error::Conflict` holding the current value when the values differ.
+/// `value` is left alone when `new` is the same as `expected`, so that only comparing doesn't
+///  reset the parts of it that are not serialized.
+/// Replacements from remotes are checked with `check_replace`.
+pub fn apply_cas<R: Reflect + Serialize, C: Context>(value: &mut R, context: &C, expected: &Value, new: &Value) -> Result<(), Error> {
+    let normalized = to_value(from_value::<R>(expected.clone())?)?;
+    let current = to_value(&*value)?;
+
+    if current != normalized {
+        return Err(Error::Conflict(current));
+    }
+
+    if new != expected {
+        let new = from_value(new.clone())?;
+        check_replace(value, context, &new)?;
+        *value = new;
+    }
+    Ok(())
+}
+
+/// Fails with `Error::ReadOnly` if `context` comes from a remote and replacing `value` with `new`
+///  changes a read-only field, as computed by `Reflect::check_readonly`.
+/// Used by `Reflect` implementations to handle `Command::Set`. Local contexts, like the one that
+///  rolls back a failed batch, can replace anything.
+pub fn check_replace<R: Reflect, C: Context>(value: &R, context: &C, new: &R) -> Result<(), Error> {
+    if context.is_remote() {
+        value.check_readonly(new)
+    } else {
+        Ok(())
+    }
+}
@@ -3,12 +3,18 @@ use super::*;
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use std::marker::PhantomData;
+use std::collections::HashMap;
 use serde_json::{Value, from_value};
 use futures::*;
+use futures::sync::oneshot::Sender;
 
 pub struct Client<T: Reflect, R: Remote> {
     value: T,
     remote: R,
+    next_request: u64,
+    requests: HashMap<u64, Sender<Result<Value, Error>>>,
+    /// Revision of the last splice received from the server.
+    revision: u64,
 }
 
 struct Connect<T: Reflect, R: Remote> {
@@ -42,6 +48,9 @@ impl<T: Reflect, R: Remote> Future for Connect<T, R> {
             Ok(Async::Ready(Client {
                 value,
                 remote: self.remote.take().unwrap(),
+                next_request: 0,
+                requests: HashMap::new(),
+                revision: 0,
             }))
         } else if self.remote.as_ref().unwrap().alive() {
             Ok(Async::NotReady)
@@ -61,12 +70,87 @@ impl<T: Reflect, R: Remote> Client<T, R> {
     }
 
     pub fn update(&mut self) {
+        self.receive(|value, cmd| value.command_str((), cmd).expect("Invalid message received"));
+    }
+
+    /// Handle the messages received from the server, passing the commands to execute to `apply`.
+    pub(crate) fn receive<F: FnMut(&mut T, &str)>(&mut self, mut apply: F) {
         for message in self.remote.iter() {
-            self.value.command_str((), message.as_str()).expect("Invalid message received");
+            if let Some((id, result)) = request::parse_response(message.as_str()) {
+                if let Some(sender) = self.requests.remove(&id) {
+                    sender.send(result).ok();
+                }
+            } else if let Some((revision, cmd)) = splice::parse_revision(message.as_str()) {
+                self.revision = revision;
+                if !cmd.is_empty() {
+                    apply(&mut self.value, cmd);
+                }
+            } else {
+                apply(&mut self.value, message.as_str());
+            }
+        }
+
+        // pending requests will never receive a response from a dead connection
+        if !self.remote.alive() {
+            self.requests.clear();
         }
     }
 
     pub fn command(&mut self, cmd: &str) -> Result<(), Error> {
-        self.remote.send(cmd)
+        let cmd = self.with_revision(cmd);
+        self.remote.send(cmd.as_str())
+    }
+
+    /// Send a command and record it in `history`, so that it can be undone with `undo`.
+    /// The inverse is computed from this client's copy of the value, so the copy should be up to
+    ///  date with the changes of other clients.
+    pub fn command_undoable(&mut self, history: &mut UndoStack, cmd: &str) -> Result<(), Error> {
+        history.record(&mut self.value, &Command::parse(cmd)?)?;
+        self.command(cmd)
+    }
+
+    /// Send the command that undoes the last change recorded in `history`.
+    /// Returns false if there is nothing to undo.
+    pub fn undo(&mut self, history: &mut UndoStack) -> Result<bool, Error> {
+        match history.undo() {
+            Some(command) => self.command(&command.to_string()).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Send the command that redoes the last change undone from `history`.
+    /// Returns false if there is nothing to redo.
+    pub fn redo(&mut self, history: &mut UndoStack) -> Result<bool, Error> {
+        match history.redo() {
+            Some(command) => self.command(&command.to_string()).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Splices are sent with the revision they are based on, so the server can transform them
+    ///  against the splices that this client hasn't received yet.
+    fn with_revision(&self, cmd: &str) -> String {
+        if splice::is_splice(cmd) {
+            splice::revision(self.revision, cmd)
+        } else {
+            cmd.to_string()
+        }
+    }
+
+    /// Send a command to the server and get a future for its outcome.
+    /// The future resolves to the serialized return value for `call:` commands, or to `null` for
+    ///  other commands, once the response is received during `update`.
+    /// Only this client receives the response.
+    pub fn request(&mut self, cmd: &str) -> Response {
+        let (sender, response) = Response::new();
+        let id = self.next_request;
+        self.next_request += 1;
+
+        match self.remote.send(request::request(id, &self.with_revision(cmd)).as_str()) {
+            Ok(()) => { self.requests.insert(id, sender); },
+            Err(e) => { sender.send(Err(e)).ok(); },
+        }
+
+        response
     }
 }
@@ -0,0 +1,68 @@
+use super::*;
+use serde_json::to_value;
+
+/// Returns the commands that turn `old` into `new`, as computed by `Reflect::diff`.
+/// Executing them in order on `old`, or on a copy of it, makes it serialize the same as `new`.
+pub fn diff<R: Reflect + Serialize>(old: &R, new: &R) -> Result<Vec<Command>, Error> {
+    old.diff(new)
+}
+
+/// Returns a single `set:` with `new` if it serializes differently from `old`, used by `Reflect`
+///  implementations that can't change their parts separately.
+pub fn diff_set<R: Reflect + Serialize>(old: &R, new: &R) -> Result<Vec<Command>, Error> {
+    let new = to_value(new)?;
+    if to_value(old)? == new {
+        return Ok(Vec::new());
+    }
+    Ok(vec![Command::Set { value: new }])
+}
+
+/// Add the commands that turn the child `old` at `element` into `new` to `commands`.
+pub fn diff_child<R: Reflect + Serialize>(element: &str, old: &R, new: &R, commands: &mut Vec<Command>) -> Result<(), Error> {
+    commands.extend(old.diff(new)?.into_iter()
+        .map(|command| Command::Path { element: element.to_string(), command: Box::new(command) }));
+    Ok(())
+}
+
+/// Returns the commands that turn the list `old` into `new`, used by `Reflect` implementations
+///  of lists that support `push:`, `pop:`, `insert:` and `remove:`.
+/// Elements that are the same at the start and the end of both lists are kept, elements in
+///  between are changed in place, and the difference in length is inserted or removed.
+pub fn diff_list<T: Reflect + Serialize>(old: &[&T], new: &[&T]) -> Result<Vec<Command>, Error> {
+    let same = |a: &T, b: &T| -> Result<bool, Error> { Ok(to_value(a)? == to_value(b)?) };
+    let shortest = old.len().min(new.len());
+
+    let mut start = 0;
+    while start < shortest && same(old[start], new[start])? {
+        start += 1;
+    }
+    let mut end = 0;
+    while end < shortest - start && same(old[old.len() - 1 - end], new[new.len() - 1 - end])? {
+        end += 1;
+    }
+
+    let old_len = old.len() - start - end;
+    let new_len = new.len() - start - end;
+    let mut commands = Vec::new();
+
+    for index in start..start + old_len.min(new_len) {
+        diff_child(&index.to_string(), old[index], new[index], &mut commands)?;
+    }
+
+    let index = start + old_len.min(new_len);
+    for _ in new_len..old_len {
+        commands.push(match end {
+            0 => Command::Pop,
+            _ => Command::Remove { key: index.into() },
+        });
+    }
+    for (index, element) in new.iter().enumerate().skip(index).take(new_len.saturating_sub(old_len)) {
+        let value = to_value(element)?;
+        commands.push(match end {
+            0 => Command::Push { value },
+            _ => Command::Insert { key: index.into(), value },
+        });
+    }
+
+    Ok(commands)
+}
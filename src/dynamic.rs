@@ -0,0 +1,292 @@
+use super::*;
+use serde::de::Error as DeError;
+use serde::ser::Error as SerError;
+use serde_json::{from_value, to_value};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+/// Name of the field that holds the registered type name in a serialized `Box<dyn DynReflect>`.
+pub const TYPE_TAG: &str = "type";
+
+/// Object safe companion of `Reflect`, for values whose concrete type is only known at runtime.
+/// Every `Reflect` type that is `Serialize` implements `DynReflect`.
+/// `Box<dyn DynReflect>` implements `Reflect`, so polymorphic children can be stored in a
+///  mirrored value. The concrete types have to be registered with `register` first, so that the
+///  box can be (de)serialized as a tagged value: `{"type":"Sword", ...}`.
+pub trait DynReflect: Any {
+    /// Same as `Reflect::command`, but with a type erased context.
+    fn command_dyn(&mut self, context: DynContext, command: &Command) -> Result<(), Error>;
+
+    /// Serialize the value, without the type tag.
+    fn to_untagged(&self) -> Result<Value, Error>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Reflect + Serialize + Any> DynReflect for T {
+    fn command_dyn(&mut self, context: DynContext, command: &Command) -> Result<(), Error> {
+        self.command(context, command)
+    }
+
+    fn to_untagged(&self) -> Result<Value, Error> {
+        Ok(to_value(self)?)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl dyn DynReflect {
+    pub fn is<T: Any>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+}
+
+/// Type erased `Context`, used by `DynReflect`.
+pub struct DynContext<'a>(Box<dyn ErasedContext<'a> + 'a>);
+
+impl<'a> DynContext<'a> {
+    pub fn new<C: Context + 'a>(context: C) -> Self {
+        DynContext(Box::new(context))
+    }
+}
+
+impl<'a> Clone for DynContext<'a> {
+    fn clone(&self) -> Self {
+        DynContext(self.0.clone_box())
+    }
+}
+
+impl<'a> Context for DynContext<'a> {
+    type Inner = Self;
+
+    fn command<R, S>(&mut self, value: &mut R, cmd: S) -> Result<(), Error> where
+        R: Reflect,
+        S: AsRef<str>
+    {
+        self.0.command(value, cmd.as_ref())
+    }
+
+    fn local_command<R, S>(&mut self, value: &mut R, cmd: S) -> Result<(), Error> where
+        R: Reflect,
+        S: AsRef<str>
+    {
+        self.0.local_command(value, cmd.as_ref())
+    }
+
+    fn with_inner<F: FnMut(Self::Inner)>(&mut self, path: &str, mut f: F) {
+        self.0.with_inner(path, &mut f)
+    }
+
+    fn respond(&mut self, value: Value) {
+        self.0.respond(value)
+    }
+
+    fn is_remote(&self) -> bool {
+        self.0.is_remote()
+    }
+
+    fn batch<F: FnMut(Self) -> Result<(), Error>>(&mut self, mut f: F) -> Result<(), Error> {
+        self.0.batch(&mut f)
+    }
+}
+
+/// Object safe version of `Context`, implemented for every `Context`.
+trait ErasedContext<'a> {
+    fn command(&mut self, value: &mut dyn ErasedValue, cmd: &str) -> Result<(), Error>;
+
+    fn local_command(&mut self, value: &mut dyn ErasedValue, cmd: &str) -> Result<(), Error>;
+
+    fn with_inner(&mut self, path: &str, f: &mut dyn FnMut(DynContext<'a>));
+
+    fn respond(&mut self, value: Value);
+
+    fn is_remote(&self) -> bool;
+
+    fn batch(&mut self, f: &mut dyn FnMut(DynContext<'a>) -> Result<(), Error>) -> Result<(), Error>;
+
+    fn clone_box(&self) -> Box<dyn ErasedContext<'a> + 'a>;
+}
+
+impl<'a, C: Context + 'a> ErasedContext<'a> for C {
+    fn command(&mut self, value: &mut dyn ErasedValue, cmd: &str) -> Result<(), Error> {
+        Context::command(self, &mut ErasedRef(value), cmd)
+    }
+
+    fn local_command(&mut self, value: &mut dyn ErasedValue, cmd: &str) -> Result<(), Error> {
+        Context::local_command(self, &mut ErasedRef(value), cmd)
+    }
+
+    fn with_inner(&mut self, path: &str, f: &mut dyn FnMut(DynContext<'a>)) {
+        Context::with_inner(self, path, |inner| f(DynContext::new(inner)))
+    }
+
+    fn respond(&mut self, value: Value) {
+        Context::respond(self, value)
+    }
+
+    fn is_remote(&self) -> bool {
+        Context::is_remote(self)
+    }
+
+    fn batch(&mut self, f: &mut dyn FnMut(DynContext<'a>) -> Result<(), Error>) -> Result<(), Error> {
+        Context::batch(self, |inner| f(DynContext::new(inner)))
+    }
+
+    fn clone_box(&self) -> Box<dyn ErasedContext<'a> + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+/// Object safe view of a `Reflect` value, so `DynContext` can pass values to the erased context.
+trait ErasedValue {
+    fn command_erased(&mut self, context: DynContext, command: &Command) -> Result<(), Error>;
+}
+
+impl<R: Reflect> ErasedValue for R {
+    fn command_erased(&mut self, context: DynContext, command: &Command) -> Result<(), Error> {
+        self.command(context, command)
+    }
+}
+
+/// Forwards commands from a `Context` back to an `ErasedValue`.
+/// Never deserialized; the `Deserialize` implementation only satisfies the bound on `Reflect`.
+struct ErasedRef<'v>(&'v mut dyn ErasedValue);
+
+impl<'v, 'de> Deserialize<'de> for ErasedRef<'v> {
+    fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(D::Error::custom("erased values can't be deserialized"))
+    }
+}
+
+impl<'v> Reflect for ErasedRef<'v> {
+    fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+        self.0.command_erased(DynContext::new(context), command)
+    }
+}
+
+type DeserializeFn = fn(Value) -> Result<Box<dyn DynReflect>, Error>;
+
+#[derive(Default)]
+struct Registry {
+    names: HashMap<TypeId, String>,
+    types: HashMap<String, DeserializeFn>,
+}
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+fn deserialize_boxed<T: DynReflect + for<'de> Deserialize<'de>>(value: Value) -> Result<Box<dyn DynReflect>, Error> {
+    Ok(Box::new(from_value::<T>(value)?))
+}
+
+/// Register a type under `name`, so it can be used in a `Box<dyn DynReflect>`.
+/// The type has to serialize to a json object, which will be tagged with `"type": name`.
+pub fn register<T: Reflect + Serialize + Any>(name: &str) {
+    let mut registry = registry().write().unwrap();
+    registry.names.insert(TypeId::of::<T>(), name.to_string());
+    registry.types.insert(name.to_string(), deserialize_boxed::<T>);
+}
+
+impl Serialize for Box<dyn DynReflect> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = registry().read().unwrap().names.get(&self.as_ref().as_any().type_id()).cloned()
+            .ok_or_else(|| S::Error::custom("type is not registered"))?;
+
+        let mut value = self.as_ref().to_untagged().map_err(S::Error::custom)?;
+        value.as_object_mut()
+            .ok_or_else(|| S::Error::custom("registered types must serialize to an object"))?
+            .insert(TYPE_TAG.to_string(), Value::String(name));
+
+        value.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn DynReflect> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+
+        let name = match value.as_object_mut().and_then(|object| object.remove(TYPE_TAG)) {
+            Some(Value::String(name)) => name,
+            _ => return Err(D::Error::custom("missing type tag")),
+        };
+
+        let deserialize = *registry().read().unwrap().types.get(&name)
+            .ok_or_else(|| D::Error::custom(format!("type {} is not registered", name)))?;
+
+        deserialize(value).map_err(D::Error::custom)
+    }
+}
+
+impl Reflect for Box<dyn DynReflect> {
+    fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+        match command {
+            Command::Set { value } => {
+                *self = from_value(value.clone())?;
+                Ok(())
+            },
+            Command::Get => apply_get(self, context),
+            Command::Cas { expected, value } => apply_cas(self, &context, expected, value),
+            Command::Batch { commands } => apply_batch(self, context, commands),
+            &_ => self.as_mut().command_dyn(DynContext::new(context), command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Sword;
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Shield {
+        block: f32,
+    }
+
+    #[test]
+    fn dynamic() {
+        register::<Sword>("Sword");
+        register::<Shield>("Shield");
+
+        let mut items: Vec<Box<dyn DynReflect>> = vec![Box::new(Sword { damage: 3 })];
+        items.command_str((), "push:{\"type\":\"Shield\",\"block\":0.5}").unwrap();
+        items.command_str((), "0/damage/set:5").unwrap();
+        items.command_str((), "1/block/set:0.75").unwrap();
+
+        assert_eq!(items[0].downcast_ref::<Sword>(), Some(&Sword { damage: 5 }));
+        assert_eq!(items[1].downcast_ref::<Shield>(), Some(&Shield { block: 0.75 }));
+
+        let reply = crate::reply::Reply::new(Vec::new());
+        items.command_str(reply.clone(), "0/call:sharpen:2").unwrap();
+        assert_eq!(reply.into_inner(), vec![("0/damage/set:7".to_string(), crate::reply::Audience::All)]);
+
+        items.command_str((), "1/set:{\"type\":\"Sword\",\"damage\":1}").unwrap();
+        assert!(items[1].is::<Sword>());
+
+        let json = serde_json::to_string(&items).unwrap();
+        assert_eq!(json, "[{\"damage\":7,\"type\":\"Sword\"},{\"damage\":1,\"type\":\"Sword\"}]");
+
+        match items.command_str((), "push:{\"type\":\"Axe\"}") {
+            Err(Error::Json(_)) => (),
+            other => panic!("expected a json error, got {:?}", other),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub enum Error {
     Json(serde_json::Error),
@@ -8,12 +10,29 @@ pub enum Error {
 
     WrongArgumentCount,
 
+    /// A command could not be parsed. `offset` is the byte offset in the command text where
+    ///  parsing failed, and `expected` describes what the parser expected there.
+    SyntaxError { offset: usize, expected: String },
+
     PathError,
 
     InvalidCommand,
 
     IncompatibleCommand,
 
+    ReadOnly,
+
+    /// An index or offset is outside of the element.
+    OutOfRange,
+
+    /// An arithmetic command produced a value that doesn't fit in the element.
+    Overflow,
+
+    /// A `Command::Cas` found a different value than expected. Holds the current value.
+    Conflict(serde_json::Value),
+
+    FunctionError(String),
+
     ConnectionDropped,
 }
 
@@ -27,4 +46,28 @@ impl From<std::num::ParseIntError> for Error {
     fn from(err: std::num::ParseIntError) -> Self {
         Error::ParseIntError(err)
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Json(err) => write!(f, "json error: {}", err),
+            Error::ParseIntError(err) => write!(f, "invalid index: {}", err),
+            Error::Command(message) => write!(f, "{}", message),
+            Error::WrongArgumentCount => write!(f, "wrong number of arguments"),
+            Error::SyntaxError { offset, expected } =>
+                write!(f, "syntax error at byte {}: expected {}", offset, expected),
+            Error::PathError => write!(f, "path does not exist"),
+            Error::InvalidCommand => write!(f, "invalid command"),
+            Error::IncompatibleCommand => write!(f, "command is not supported by this element"),
+            Error::ConnectionDropped => write!(f, "connection dropped"),
+            Error::ReadOnly => write!(f, "element is read only"),
+            Error::OutOfRange => write!(f, "index out of range"),
+            Error::Overflow => write!(f, "arithmetic overflow"),
+            Error::Conflict(current) => write!(f, "conflict, the current value is {}", current),
+            Error::FunctionError(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error { }
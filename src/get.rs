@@ -0,0 +1,9 @@
+use super::*;
+use serde_json::to_value;
+
+/// Pass the serialized `value` to `Context::respond`, used by `Reflect` implementations to handle
+///  `Command::Get`.
+pub fn apply_get<R: Reflect + Serialize, C: Context>(value: &R, mut context: C) -> Result<(), Error> {
+    context.respond(to_value(value)?);
+    Ok(())
+}
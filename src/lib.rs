@@ -5,12 +5,28 @@ extern crate mirror_derive;
 pub mod error;
 pub mod list;
 pub mod map;
+pub mod set;
 pub mod primitive;
 pub mod option;
+pub mod tuple;
+pub mod value;
+pub mod boxed;
 pub mod hidden;
+pub mod dynamic;
+pub mod builder;
+pub mod batch;
+pub mod cas;
+pub mod get;
+pub mod diff;
+pub mod patch;
+pub mod splice;
+pub mod undo;
+mod parser;
 
 pub mod remote;
+pub mod request;
 pub mod client;
+pub mod untyped_client;
 mod reply;
 pub mod private_server;
 pub mod shared_server;
@@ -18,35 +34,58 @@ pub mod shared_server;
 pub use mirror_derive::*;
 
 pub use self::error::*;
-pub use self::list::*;
-pub use self::map::*;
+pub use self::list::Array;
 pub use self::primitive::*;
-pub use self::option::*;
 pub use self::hidden::*;
+pub use self::dynamic::*;
+pub use self::builder::*;
+pub use self::batch::*;
+pub use self::cas::*;
+pub use self::get::*;
+pub use self::diff::*;
+pub use self::patch::*;
+pub use self::splice::{transform, SPLICE_HISTORY};
+pub use self::parser::MAX_PATH_DEPTH;
+pub use self::undo::*;
 pub use self::remote::*;
+pub use self::request::*;
 pub use self::client::*;
+pub use self::untyped_client::*;
 pub use self::private_server::*;
 pub use self::shared_server::*;
 
 use serde::*;
-use serde_json::{Value, StreamDeserializer};
-use serde_json::de::StrRead;
+use serde_json::Value;
+use std::fmt;
 
 /// A single command that can be executed on a `Reflect` object.
 /// Commands can be wrapped inside zero or more `Command::Path` elements that tell `Reflect`
 /// implementations how to navigate to their children.
 /// Each variant has an associated syntax that you can use when calling `Command::parse()`.
 /// The bits that are between {} represent either another command {sub command} or json {x-json}.
-#[derive(Debug)]
+/// `Command` implements `Display`, which gives the same syntax that `Command::parse()` accepts.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     /// Traverse to the child element denoted by `element`.
     /// After traversing, execute `command` on the child.
+    /// An element that is a json string, like the keys of a map, is written as is: `"a/b"/set:1`.
+    /// In other elements, `/`, `:`, `\` and `"` have to be escaped with a `\`.
     /// syntax: `element/{sub command}`
     Path {
         element: String,
         command: Box<Command>,
     },
 
+    /// Traverse to every child element named in `elements`, or to every child if it is `None`,
+    ///  and execute `command` on each of them as a single unit, like `Batch`.
+    /// Supported by lists, maps and derived structs. On structs, `*` skips fields that are read
+    ///  only when `command` is a mutation.
+    /// syntax: `*/{sub command}`, or with the elements between literal braces: `{a,b}/{sub command}`
+    Each {
+        elements: Option<Vec<String>>,
+        command: Box<Command>,
+    },
+
     /// Overwrite the entire value of the current element by deserializing `value`.
     /// syntax: `set:{value-json}`
     Set {
@@ -67,34 +106,129 @@ pub enum Command {
 
     /// Insert an element deserialized from `value` in a container
     ///  using the key deserialized from `key`.
-    /// Requires the current element to be a list or map.
-    /// syntax: `insert:{key-json} {value-json}`
+    /// Requires the current element to be a list, map or set. For lists, `key` is the index that
+    ///  the element will have, which can be at most the length of the list. Sets insert `key`
+    ///  itself, so the value can be left out, in which case it is `null`.
+    /// syntax: `insert:{key-json} {value-json}` or `insert:{key-json}`
     Insert {
         key: Value,
         value: Value,
     },
 
     /// Remove an element from a container using the key deserialized from `key`.
-    /// Requires the current element to be a list or map.
+    /// Requires the current element to be a list, map or set. Sets remove `key` itself.
     /// syntax: `remove:{key-json}`
     Remove {
         key: Value,
     },
 
+    /// Swap the elements at indices `a` and `b` of a list.
+    /// syntax: `swap:{a} {b}`
+    Swap {
+        a: usize,
+        b: usize,
+    },
+
+    /// Move the element at index `from` of a list to index `to`, shifting the elements between.
+    /// syntax: `move:{from} {to}`
+    Move {
+        from: usize,
+        to: usize,
+    },
+
+    /// Shorten a list to `len` elements. Has no effect on shorter lists.
+    /// syntax: `truncate:{len}`
+    Truncate {
+        len: usize,
+    },
+
+    /// Remove all elements from a list.
+    /// syntax: `clear:`
+    Clear,
+
+    /// Append the elements deserialized from `value`, which has to be a json array, to a list.
+    /// syntax: `extend:{values-json}`
+    Extend {
+        value: Value,
+    },
+
     /// Call a function on the current element.
     /// This requires the current element to export the desired function using the #ReflectFn(..)
-    /// proc-macro.
+    /// proc-macro, or the #[reflect_methods] attribute on the impl block that defines it.
     /// The number of json arguments should match the argument count of the function.
     /// The `Context` argument does not count.
+    /// The return value of the function is serialized and passed to `Context::respond`.
+    /// Functions that return a `Result` fail the command with `Error::FunctionError` on `Err`.
+    /// With `#[ReflectFn(..)]` this has to be enabled with `result="true"` on the `Fn`.
     /// syntax: `call:{name}:{arg0-json} {arg1-json} {arg2-json}...`
     Call {
         key: String,
         arguments: Vec<Value>,
-    }
+    },
+
+    /// Add `value` to the current element, which has to be a number.
+    /// Unlike `Set`, concurrent additions from several remotes all take effect.
+    /// Fails with `Error::Overflow` if the result doesn't fit in the element.
+    /// syntax: `add:{value-json}`
+    Add {
+        value: Value,
+    },
+
+    /// Multiply the current element, which has to be a number, by `value`.
+    /// Fails with `Error::Overflow` if the result doesn't fit in the element.
+    /// syntax: `mul:{value-json}`
+    Mul {
+        value: Value,
+    },
+
+    /// Overwrite the current element with `value` if `value` is smaller.
+    /// syntax: `min:{value-json}`
+    Min {
+        value: Value,
+    },
+
+    /// Overwrite the current element with `value` if `value` is larger.
+    /// syntax: `max:{value-json}`
+    Max {
+        value: Value,
+    },
+
+    /// Replace `delete` chars of the current element, which has to be a `String`, starting at the
+    ///  char `offset`, with `insert`. Fails with `Error::OutOfRange` if the chars don't exist.
+    /// `SharedServer` transforms the offsets of splices that clients made concurrently.
+    /// syntax: `splice:{offset} {delete} {insert-json}`
+    Splice {
+        offset: usize,
+        delete: usize,
+        insert: String,
+    },
+
+    /// Serialize the current element and pass it to `Context::respond`, without changing anything.
+    /// Servers send the value only to the remote that requested it.
+    /// syntax: `get:`
+    Get,
+
+    /// Overwrite the current element with `value`, but only if it currently serializes equal to
+    ///  `expected`. Otherwise the command fails with `Error::Conflict`, holding the current value.
+    /// When `value` is the same as `expected` the element is only compared, and the command is
+    ///  not a mutation, like the `test` operation of json patch.
+    /// syntax: `cas:{expected-json} {value-json}`
+    Cas {
+        expected: Value,
+        value: Value,
+    },
+
+    /// Execute `commands` in order on the current element, as a single unit.
+    /// If one of the commands fails, the element is rolled back to its state before the batch.
+    /// Networked contexts send the messages scheduled by the commands as a single batch.
+    /// syntax: `batch:[{command-json-string}, {command-json-string}...]`
+    Batch {
+        commands: Vec<Command>,
+    },
 }
 
 /// Context for executing commands
-pub trait Context {
+pub trait Context: Clone {
     type Inner: Context;
 
     /// Immediately run a command on the provided value. This value should be self.
@@ -106,11 +240,29 @@ pub trait Context {
     fn local_command<R: Reflect, S: AsRef<str>>(&mut self, value: &mut R, cmd: S) -> Result<(), Error>;
 
     /// Take the context a level deeper. This is used by `Reflect` when traversing a path.
-    /// Network contexts can use this to keep track of the root
+    /// Network contexts can use this to keep track of the root.
+    /// An empty `path` yields a context at the same level, which is used for flattened fields.
     fn with_inner<F: FnMut(Self::Inner)>(&mut self, path: &str, f: F);
+
+    /// Deliver the serialized return value of a `Command::Call` to whoever issued the command.
+    /// Contexts that are not part of a network ignore the response.
+    fn respond(&mut self, _value: Value) { }
+
+    /// Returns whether the commands executed with this context come from a remote.
+    /// Remotes can't replace a value as a whole if that changes a field marked with
+    ///  `#[reflect(readonly)]`, as checked by `check_replace`.
+    fn is_remote(&self) -> bool { false }
+
+    /// Run `f` as a single unit, used by `Command::Batch`.
+    /// Network contexts discard the messages scheduled within `f` if it fails, and send them as
+    ///  one `Command::Batch` if it succeeds.
+    fn batch<F: FnMut(Self) -> Result<(), Error>>(&mut self, mut f: F) -> Result<(), Error> {
+        f(self.clone())
+    }
 }
 
 /// Trait for executing commands
+#[diagnostic::on_unimplemented(note = "arrays of more than 32 elements don't implement `Reflect`, use `mirror::Array<T, N>` instead")]
 pub trait Reflect: for<'de> Deserialize<'de> {
     /// Executes the command on this object. If the command is executed successfully, Ok will be
     /// returned. Otherwise, an Err with the error will be returned.
@@ -118,10 +270,42 @@ pub trait Reflect: for<'de> Deserialize<'de> {
 
     /// Same as `command`, but with a command as `&str` that still has to be parsed
     fn command_str<C: Context>(&mut self, context: C, command: &str) -> Result<(), Error> {
-        Ok(self.command(context, &Command::parse(command)?)?)
+        self.command(context, &Command::parse(command)?)
+    }
+
+    /// Returns the commands that turn this value into `new`, so that changes made directly to a
+    ///  value can be sent to others. The default is a `set:` of the whole value, if it changed.
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> where Self: Serialize {
+        diff_set(self, new)
+    }
+
+    /// Fails with `Error::ReadOnly` if replacing this value with `new` changes a field marked with
+    ///  `#[reflect(readonly)]`, in this value or in the children it always has, like the fields
+    ///  of a struct. The elements of lists, maps and options can be removed and inserted by
+    ///  remotes anyway, so they are not compared. The default has no read-only fields.
+    fn check_readonly(&self, _new: &Self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Same as `command`, but returns the command that undoes it, as computed by `inverse`.
+    fn command_with_inverse<C: Context>(&mut self, context: C, command: &Command) -> Result<Command, Error> {
+        let inverse = inverse(self, command)?;
+        self.command(context, command)?;
+        Ok(inverse)
     }
 }
 
+/// Fallback for derived `Reflect` implementations that don't export any functions.
+/// Derived implementations dispatch `Command::Call` to `_call`, which resolves to the inherent
+///  function generated by `#[ReflectFn(..)]` or `#[reflect_methods]` when either is present.
+pub trait NoFunctions {
+    fn _call<C: Context>(&mut self, _key: &str, _context: C, _arguments: &[Value]) -> Result<Value, Error> {
+        Err(Error::InvalidCommand)
+    }
+}
+
+impl<T: ?Sized> NoFunctions for T { }
+
 /// Dummy context for when no context is needed
 impl Context for () {
     type Inner = ();
@@ -145,53 +329,128 @@ impl Context for () {
 
 impl Command {
     /// Parse a `Command` from a `&str`.
-    /// If there is an error during parsing, an Err will be returned.
+    /// If there is an error during parsing, an `Error::SyntaxError` with the byte offset of the
+    ///  problem will be returned. Parsing never panics, so untrusted input can be parsed safely.
     /// If the command is valid, Ok will be returned.
     pub fn parse(command: &str) -> Result<Self, Error> {
-        if command.starts_with("set:") {
-            let mut stream = StreamDeserializer::new(StrRead::new(command.split_at("set:".len()).1));
-            Ok(Command::Set {
-                value: stream.next().ok_or(Error::WrongArgumentCount)??,
-            })
-        } else if command.starts_with("push:") {
-            let mut stream = StreamDeserializer::new(StrRead::new(command.split_at("push:".len()).1));
-            Ok(Command::Push {
-                value: stream.next().ok_or(Error::WrongArgumentCount)??,
-            })
-        } else if command.starts_with("pop:") {
-            Ok(Command::Pop)
-        } else if command.starts_with("insert:") {
-            let mut stream = StreamDeserializer::new(StrRead::new(command.split_at("insert:".len()).1));
-            Ok(Command::Insert {
-                key: stream.next().ok_or(Error::WrongArgumentCount)??,
-                value: stream.next().ok_or(Error::WrongArgumentCount)??,
-            })
-        } else if command.starts_with("remove:") {
-            let mut stream = StreamDeserializer::new(StrRead::new(command.split_at("remove:".len()).1));
-            Ok(Command::Remove {
-                key: stream.next().ok_or(Error::WrongArgumentCount)??,
-            })
-        } else if command.starts_with("call:") {
-            let cmd = command.split_at("call:".len()).1;
-            let (key, args) = cmd.split_at(cmd.find(':').unwrap());
-
-            let stream = StreamDeserializer::new(StrRead::new(&args[1..]));
-            let mut arguments = Vec::new();
-            for result in stream {
-                arguments.push(result?);
-            }
+        parser::parse(command)
+    }
+
+    /// Returns whether executing this command might modify the element at the end of its path.
+    /// Fields marked with `#[reflect(readonly)]` reject any command for which this is true.
+    pub fn is_mutation(&self) -> bool {
+        match self {
+            Command::Path { command, .. } | Command::Each { command, .. } => command.is_mutation(),
+            Command::Get => false,
+            Command::Cas { expected, value } => expected != value,
+            Command::Batch { commands } => commands.iter().any(Command::is_mutation),
+            &_ => true,
+        }
+    }
+
+    /// Returns whether this command calls a function somewhere.
+    pub(crate) fn calls(&self) -> bool {
+        match self {
+            Command::Path { command, .. } | Command::Each { command, .. } => command.calls(),
+            Command::Call { .. } => true,
+            Command::Batch { commands } => commands.iter().any(Command::calls),
+            &_ => false,
+        }
+    }
 
-            Ok(Command::Call {
-                key: key.into(),
-                arguments,
-            })
-        } else if command.contains("/") {
-            Ok(Command::Path {
-                element: command.split('/').next().unwrap().into(),
-                command: Box::new(Command::parse(command.split_at(command.find("/").unwrap() + 1).1)?),
-            })
-        } else {
-            Err(Error::InvalidCommand)
+    /// Returns the path of this command and the command at the end of it.
+    /// The path stops at the first `Command::Each`, which is returned as the command.
+    pub(crate) fn split_path(&self) -> (Vec<&str>, &Command) {
+        let mut path = Vec::new();
+        let mut command = self;
+        while let Command::Path { element, command: next } = command {
+            path.push(element.as_str());
+            command = next;
+        }
+        (path, command)
+    }
+
+    /// Builds the command that executes `command` at the end of `path`.
+    pub(crate) fn with_path(path: &[&str], command: Command) -> Command {
+        path.iter().rev().fold(command, |command, element| Command::Path {
+            element: element.to_string(),
+            command: Box::new(command),
+        })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Path { .. } | Command::Each { .. } => {
+                // a leading empty element would read as a json pointer, so the path is written as one
+                let pointer = matches!(self, Command::Path { element, .. } if element.is_empty());
+                if pointer {
+                    write!(f, "/")?;
+                }
+
+                let mut command = self;
+                loop {
+                    command = match command {
+                        Command::Path { element, command } => {
+                            parser::write_element(f, element, pointer)?;
+                            command
+                        },
+                        Command::Each { elements: None, command } => {
+                            write!(f, "*")?;
+                            command
+                        },
+                        Command::Each { elements: Some(elements), command } => {
+                            write!(f, "{{")?;
+                            for (i, element) in elements.iter().enumerate() {
+                                if i > 0 {
+                                    write!(f, ",")?;
+                                }
+                                parser::write_element(f, element, pointer)?;
+                            }
+                            write!(f, "}}")?;
+                            command
+                        },
+                        command => return write!(f, "{}", command),
+                    };
+                    write!(f, "/")?;
+                }
+            },
+            Command::Set { value } => write!(f, "set:{}", value),
+            Command::Push { value } => write!(f, "push:{}", value),
+            Command::Pop => write!(f, "pop:"),
+            Command::Insert { key, value: Value::Null } => write!(f, "insert:{}", key),
+            Command::Insert { key, value } => write!(f, "insert:{} {}", key, value),
+            Command::Remove { key } => write!(f, "remove:{}", key),
+            Command::Swap { a, b } => write!(f, "swap:{} {}", a, b),
+            Command::Move { from, to } => write!(f, "move:{} {}", from, to),
+            Command::Truncate { len } => write!(f, "truncate:{}", len),
+            Command::Clear => write!(f, "clear:"),
+            Command::Extend { value } => write!(f, "extend:{}", value),
+            Command::Call { key, arguments } => {
+                write!(f, "call:")?;
+                parser::write_element(f, key, false)?;
+                write!(f, ":")?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                Ok(())
+            },
+            Command::Add { value } => write!(f, "add:{}", value),
+            Command::Mul { value } => write!(f, "mul:{}", value),
+            Command::Min { value } => write!(f, "min:{}", value),
+            Command::Max { value } => write!(f, "max:{}", value),
+            Command::Splice { offset, delete, insert } =>
+                write!(f, "splice:{} {} {}", offset, delete, Value::String(insert.clone())),
+            Command::Get => write!(f, "get:"),
+            Command::Cas { expected, value } => write!(f, "cas:{} {}", expected, value),
+            Command::Batch { commands } => {
+                let commands: Vec<Value> = commands.iter().map(|command| Value::String(command.to_string())).collect();
+                write!(f, "batch:{}", Value::Array(commands))
+            },
         }
     }
 }
@@ -199,16 +458,57 @@ impl Command {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::rc::Rc;
+    use std::collections::{BTreeSet, HashSet};
+    use std::cell::{Cell, RefCell};
+    use std::collections::{HashMap, VecDeque};
+    use futures::Future;
+    use proptest::prelude::*;
+
+    /// In-memory `Remote` for testing the servers and clients.
+    pub struct Pipe {
+        inbox: Rc<RefCell<VecDeque<String>>>,
+        outbox: Rc<RefCell<VecDeque<String>>>,
+        alive: Rc<Cell<bool>>,
+    }
+
+    pub fn pipe() -> (Pipe, Pipe) {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        let alive = Rc::new(Cell::new(true));
+        (Pipe { inbox: a.clone(), outbox: b.clone(), alive: alive.clone() },
+         Pipe { inbox: b, outbox: a, alive })
+    }
 
-    #[derive(Deserialize, Reflect)]
+    impl Remote for Pipe {
+        fn close(&mut self) { self.alive.set(false); }
+
+        fn alive(&self) -> bool { self.alive.get() }
+
+        fn send(&mut self, message: &str) -> Result<(), Error> {
+            if self.alive.get() {
+                self.outbox.borrow_mut().push_back(message.to_string());
+                Ok(())
+            } else {
+                Err(Error::ConnectionDropped)
+            }
+        }
+
+        fn recv(&mut self) -> Option<String> {
+            self.inbox.borrow_mut().pop_front()
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
     pub struct FooBar {
         foo: Foo
     }
 
+    #[derive(Serialize, Deserialize, Reflect)]
     #[ReflectFn(
-        Fn(name="set_bar", args="2")
+        Fn(name="set_bar", args="2"),
+        Fn(name="check_bar", args="1", result="true")
     )]
-    #[derive(Deserialize, Reflect)]
     pub struct Foo {
         bar: Vec<usize>,
     }
@@ -218,6 +518,10 @@ mod tests {
             println!("Bar should be set to {} with bar2 {}", bar, bar2);
             //self.bar = bar;
         }
+
+        fn check_bar<C: Context>(&mut self, _context: C, index: usize) -> Result<usize, Error> {
+            self.bar.get(index).cloned().ok_or(Error::PathError)
+        }
     }
 
     #[test]
@@ -227,9 +531,1217 @@ mod tests {
         test.command_str((), "foo/bar/1/set:5").unwrap();
         test.command_str((), "foo/call:set_bar:16 \"test\"").unwrap();
         assert_eq!(test.foo.bar[1], 5);
+        test.command_str((), "foo/call:check_bar:2").unwrap();
+        match test.command_str((), "foo/call:check_bar:3") {
+            Err(Error::FunctionError(_)) => (),
+            other => panic!("expected a function error, got {:?}", other),
+        }
 
         let mut test = String::from("test");
         test.command_str((), "set:\"foo bar\"").unwrap();
         assert_eq!(test, String::from("foo bar"));
     }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    pub struct Meters(f64);
+
+    impl Primitive for Meters { }
+
+    impl Reflect for Meters {
+        fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+            apply_primitive(self, context, command)
+        }
+    }
+
+    #[test]
+    fn custom_primitives() {
+        fn primitive<T: Primitive>(_: &T) { }
+        primitive(&(1u8, "two".to_string()));
+
+        let mut test = Meters(1.0);
+        test.command_str((), "set:2.5").unwrap();
+        assert_eq!(test, Meters(2.5));
+        assert!(matches!(test.command_str((), "a/set:1"), Err(Error::IncompatibleCommand)));
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Attributes {
+        #[reflect(rename = "hp")]
+        pub(crate) health: u32,
+        #[reflect(readonly)]
+        pub(crate) level: u32,
+        #[reflect(skip)]
+        #[serde(skip)]
+        pub(crate) cache: std::cell::Cell<u32>,
+    }
+
+    #[test]
+    fn field_attributes() {
+        let mut test = Attributes { health: 10, level: 1, cache: Default::default() };
+        test.command_str((), "hp/set:20").unwrap();
+        assert_eq!(test.health, 20);
+
+        match test.command_str((), "health/set:30") {
+            Err(Error::PathError) => (),
+            other => panic!("expected a path error, got {:?}", other),
+        }
+        match test.command_str((), "level/set:2") {
+            Err(Error::ReadOnly) => (),
+            other => panic!("expected a read only error, got {:?}", other),
+        }
+        match test.command_str((), "cache/set:2") {
+            Err(Error::PathError) => (),
+            other => panic!("expected a path error, got {:?}", other),
+        }
+        assert_eq!(test.level, 1);
+        assert_eq!(test.cache.get(), 0);
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub enum Rank {
+        Ranked {
+            #[reflect(readonly)]
+            level: u32,
+        },
+        Unranked,
+    }
+
+    #[test]
+    fn readonly_replacements() {
+        let remote = crate::reply::Reply::from_remote(Vec::new());
+
+        // remotes can replace a value that has read-only fields, as long as they stay the same
+        let mut test = Attributes { health: 10, level: 1, cache: Default::default() };
+        test.command_str(remote.clone(), "set:{\"health\":20,\"level\":1}").unwrap();
+        assert_eq!((test.health, test.level), (20, 1));
+        assert!(matches!(test.command_str(remote.clone(), "set:{\"health\":20,\"level\":99}"), Err(Error::ReadOnly)));
+        assert!(matches!(test.command_str(remote.clone(), "cas:{\"health\":20,\"level\":1} {\"health\":5,\"level\":99}"), Err(Error::ReadOnly)));
+        assert!(matches!(test.command_str(remote.clone(), "batch:[\"hp/set:1\",\"set:{\\\"health\\\":2,\\\"level\\\":99}\"]"), Err(Error::ReadOnly)));
+        assert_eq!((test.health, test.level), (20, 1));
+
+        // local contexts, like the rollback of a batch, are not restricted
+        test.command_str((), "set:{\"health\":20,\"level\":99}").unwrap();
+        assert_eq!(test.level, 99);
+
+        // children that are always there are compared as well
+        let mut test = (Box::new(Attributes { health: 10, level: 1, cache: Default::default() }), 0u8);
+        assert!(matches!(test.command_str(remote.clone(), "set:[{\"health\":10,\"level\":2},1]"), Err(Error::ReadOnly)));
+        test.command_str(remote.clone(), "set:[{\"health\":10,\"level\":1},1]").unwrap();
+        assert_eq!(test.1, 1);
+
+        // enums can change their variant, but not the read-only fields of the current one
+        let mut test = Rank::Ranked { level: 1 };
+        assert!(matches!(test.command_str(remote.clone(), "set:{\"Ranked\":{\"level\":2}}"), Err(Error::ReadOnly)));
+        test.command_str(remote.clone(), "set:\"Unranked\"").unwrap();
+        test.command_str(remote.clone(), "set:{\"Ranked\":{\"level\":2}}").unwrap();
+        assert!(matches!(test, Rank::Ranked { level: 2 }));
+    }
+
+    fn arb_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<u64>().prop_map(Value::from),
+            any::<f64>().prop_filter("json numbers are finite", |f| f.is_finite()).prop_map(Value::from),
+            ".*".prop_map(Value::from),
+        ];
+        leaf.prop_recursive(4, 32, 4, |inner| prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4).prop_map(Value::from),
+            prop::collection::btree_map(".*", inner, 0..4)
+                .prop_map(|map| Value::Object(map.into_iter().collect())),
+        ])
+    }
+
+    fn arb_command() -> impl Strategy<Value = Command> {
+        let leaf = prop_oneof![
+            arb_value().prop_map(|value| Command::Set { value }),
+            arb_value().prop_map(|value| Command::Push { value }),
+            Just(Command::Pop),
+            (arb_value(), arb_value()).prop_map(|(key, value)| Command::Insert { key, value }),
+            (arb_value(), arb_value()).prop_map(|(expected, value)| Command::Cas { expected, value }),
+            (any::<usize>(), any::<usize>(), ".*")
+                .prop_map(|(offset, delete, insert)| Command::Splice { offset, delete, insert }),
+            arb_value().prop_map(|value| Command::Add { value }),
+            arb_value().prop_map(|value| Command::Mul { value }),
+            arb_value().prop_map(|value| Command::Min { value }),
+            arb_value().prop_map(|value| Command::Max { value }),
+            arb_value().prop_map(|key| Command::Remove { key }),
+            (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Command::Swap { a, b }),
+            (any::<usize>(), any::<usize>()).prop_map(|(from, to)| Command::Move { from, to }),
+            any::<usize>().prop_map(|len| Command::Truncate { len }),
+            Just(Command::Clear),
+            Just(Command::Get),
+            arb_value().prop_map(|value| Command::Extend { value }),
+            (".{0,8}", prop::collection::vec(arb_value(), 0..4))
+                .prop_map(|(key, arguments)| Command::Call { key, arguments }),
+        ];
+        leaf.prop_recursive(4, 16, 3, |inner| prop_oneof![
+            (prop_oneof![".{0,8}", "\"[a-z/:\\\\]{0,4}\""], inner.clone()).prop_map(|(element, command)| Command::Path {
+                element,
+                command: Box::new(command),
+            }),
+            (prop::option::of(prop::collection::vec(".{0,8}", 1..3)), inner.clone())
+                .prop_map(|(elements, command)| Command::Each { elements, command: Box::new(command) }),
+            prop::collection::vec(inner, 0..3).prop_map(|commands| Command::Batch { commands }),
+        ])
+    }
+
+    proptest! {
+        #[test]
+        fn display_round_trip(command in arb_command()) {
+            let text = command.to_string();
+            prop_assert_eq!(Command::parse(&text).unwrap(), command, "{}", text);
+        }
+
+        #[test]
+        fn parse_never_panics(text in ".*") {
+            let _ = Command::parse(&text);
+        }
+    }
+
+    #[test]
+    fn display() {
+        let command = FooBar::cmd().foo().bar().at(1).set(&5).unwrap();
+        assert_eq!(command.to_string(), "foo/bar/1/set:5");
+        assert_eq!(Command::parse("foo/call:set_bar:16 \"test\"").unwrap().to_string(),
+                   "foo/call:set_bar:16 \"test\"");
+        assert_eq!(Command::parse("call:reset:").unwrap().to_string(), "call:reset:");
+        assert_eq!(Command::parse("insert:\"a\" [1,2]").unwrap().to_string(), "insert:\"a\" [1,2]");
+    }
+
+    fn syntax_error(text: &str) -> (usize, String) {
+        match Command::parse(text) {
+            Err(Error::SyntaxError { offset, expected }) => (offset, expected),
+            other => panic!("expected a syntax error for {}, got {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        let mut map: HashMap<String, i32> = HashMap::new();
+        map.command_str((), "insert:\"a/b:c\" 1").unwrap();
+        map.command_str((), "\"a/b:c\"/set:2").unwrap();
+        assert_eq!(map["a/b:c"], 2);
+
+        assert_eq!(Command::parse("a\\/b\\:c/pop:").unwrap(), Command::Path {
+            element: "a/b:c".into(),
+            command: Box::new(Command::Pop),
+        });
+        assert_eq!(Command::parse("call:a\\:b:1").unwrap(), Command::Call {
+            key: "a:b".into(),
+            arguments: vec![Value::from(1)],
+        });
+
+        assert_eq!(syntax_error("foo"), (3, "'/' or ':'".into()));
+        assert_eq!(syntax_error("foo/bar:1").0, 4);
+        assert_eq!(syntax_error("set:1 2").0, 6);
+        assert_eq!(syntax_error("set:").0, 4);
+        assert_eq!(syntax_error("insert:").0, 7);
+        assert_eq!(syntax_error("insert:1 2 3").0, 7);
+        assert_eq!(syntax_error("call:reset").0, 10);
+        assert_eq!(syntax_error("\"a\"b/pop:").0, 3);
+        assert_eq!(syntax_error("a\\").0, 2);
+        assert_eq!(syntax_error(&"a/".repeat(MAX_PATH_DEPTH + 1)).0, 2 * (MAX_PATH_DEPTH + 1));
+        assert!(Command::parse(&format!("{}pop:", "a/".repeat(MAX_PATH_DEPTH))).is_ok());
+    }
+
+    #[test]
+    fn builders() {
+        let mut test = FooBar { foo: Foo { bar: vec![0, 1, 2] } };
+        test.command((), &FooBar::cmd().foo().bar().at(1).set(&5).unwrap()).unwrap();
+        test.command((), &FooBar::cmd().foo().bar().push(&7).unwrap()).unwrap();
+        test.command((), &FooBar::cmd().foo().bar().remove(0)).unwrap();
+        assert_eq!(test.foo.bar, vec![5, 2, 7]);
+
+        let mut test = Player {
+            player_name: "bram".into(),
+            health: 10,
+            position: Position { x: 0, y: 0 },
+            state: PlayerState::InGame { team_id: 0 },
+        };
+        test.command((), &Player::cmd().player_name().set(&"kurble".to_string()).unwrap()).unwrap();
+        test.command((), &Player::cmd().health().set(&5).unwrap()).unwrap();
+        test.command((), &Player::cmd().position().y().set(&3).unwrap()).unwrap();
+        test.command((), &Player::cmd().state().in_game().team_id().set(&2).unwrap()).unwrap();
+        assert_eq!(test.player_name, "kurble");
+        assert_eq!(test.health, 5);
+        assert_eq!(test.position.y, 3);
+        assert_eq!(test.state, PlayerState::InGame { team_id: 2 });
+
+        let mut test = Shape::Rect { width: 1.0, tags: vec![] };
+        test.command((), &Shape::cmd().rect().tags().push(&"big".to_string()).unwrap()).unwrap();
+        test.command((), &Shape::cmd().rect().width().set(&2.0).unwrap()).unwrap();
+        assert_eq!(test, Shape::Rect { width: 2.0, tags: vec!["big".into()] });
+        test.command((), &Shape::cmd().set(&Shape::Circle(1.0)).unwrap()).unwrap();
+        test.command((), &Shape::cmd().circle()._0().set(&4.0).unwrap()).unwrap();
+        assert_eq!(test, Shape::Circle(4.0));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Reflect)]
+    #[reflect(builder = "Step")]
+    pub enum Move {
+        Move { x: i32 },
+        Type(u8),
+    }
+
+    /// A user type with the name of a default builder, which the attribute avoids.
+    #[allow(dead_code)]
+    pub struct MoveCmd;
+
+    #[test]
+    fn builder_names() {
+        let mut test = Move::Move { x: 0 };
+        let command: Step = Move::cmd();
+        test.command((), &command.move_().x().set(&3).unwrap()).unwrap();
+        assert_eq!(test, Move::Move { x: 3 });
+
+        let mut test = Move::Type(0);
+        let command: StepType = Move::cmd().type_();
+        test.command((), &command._0().set(&7).unwrap()).unwrap();
+        assert_eq!(test, Move::Type(7));
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Wallet {
+        pub(crate) gold: u32,
+    }
+
+    #[reflect_methods]
+    impl Wallet {
+        fn can_afford(&mut self, _context: impl Context, price: u32) -> bool {
+            price <= self.gold
+        }
+
+        fn spend<C: Context>(&mut self, mut context: C, price: u32) -> u32 {
+            let gold = self.gold - price;
+            context.command(self, format!("gold/set:{}", gold)).unwrap();
+            gold
+        }
+
+        fn spend_checked<C: Context>(&mut self, context: C, price: u32) -> Result<u32, &'static str> {
+            if price > self.gold {
+                Err("not enough gold")
+            } else {
+                Ok(self.spend(context, price))
+            }
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(Wallet { gold: 10 }, accept);
+
+        let (a, b) = pipe();
+        let (c, d) = pipe();
+        listener.send(a).unwrap();
+        listener.send(c).unwrap();
+        server.update();
+
+        let mut client = Client::<Wallet, _>::new(b).wait().unwrap();
+        let observer_inbox = d.inbox.clone();
+        let mut observer = Client::<Wallet, _>::new(d).wait().unwrap();
+
+        let afford = client.request("call:can_afford:20");
+        let spend = client.request("call:spend:4");
+        let missing = client.request("call:steal:4");
+        server.update();
+        client.update();
+        assert_eq!(observer_inbox.borrow().len(), 1);
+        observer.update();
+
+        assert_eq!(afford.wait().unwrap(), Value::Bool(false));
+        assert_eq!(spend.wait().unwrap(), Value::from(6));
+        match missing.wait() {
+            Err(Error::Command(_)) => (),
+            other => panic!("expected a command error, got {:?}", other),
+        }
+
+        let broke = client.request("call:spend_checked:7");
+        server.update();
+        client.update();
+        match broke.wait() {
+            Err(Error::Command(ref message)) if message == "not enough gold" => (),
+            other => panic!("expected a command error, got {:?}", other),
+        }
+
+        // the changes are broadcast, but the responses only go to the origin
+        assert_eq!(client.gold, 6);
+        assert_eq!(observer.gold, 6);
+        assert!(observer_inbox.borrow().is_empty());
+        assert_eq!(server.clients(), 2);
+
+        // failing commands outside of a request disconnect the client
+        client.command("call:spend_checked:7").unwrap();
+        server.update();
+        assert_eq!(server.clients(), 1);
+        assert!(!client.alive());
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Bank {
+        #[serde(rename = "main/vault")]
+        vault: Wallet,
+    }
+
+    #[test]
+    fn escaped_replies() {
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(Bank { vault: Wallet { gold: 10 } }, accept);
+        let (a, b) = pipe();
+        let (c, d) = pipe();
+        listener.send(a).unwrap();
+        listener.send(c).unwrap();
+        server.update();
+        let mut client = Client::<Bank, _>::new(b).wait().unwrap();
+        let observer_inbox = d.inbox.clone();
+        let mut observer = Client::<Bank, _>::new(d).wait().unwrap();
+
+        // the changes made by the call are sent with the element escaped again
+        client.command("main\\/vault/call:spend:1").unwrap();
+        server.update();
+        assert_eq!(*observer_inbox.borrow(), vec!["main\\/vault/gold/set:9".to_string()]);
+        observer.update();
+        assert_eq!(observer.vault.gold, 9);
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Hero {
+        pub(crate) inventory: Vec<String>,
+        pub(crate) equipment: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Labeled<T> {
+        label: String,
+        value: T,
+    }
+
+    /// Only takes `set:`, so it doesn't have to be serializable.
+    #[derive(Deserialize)]
+    pub struct Tally(u32);
+
+    impl Reflect for Tally {
+        fn command<C: Context>(&mut self, _context: C, command: &Command) -> Result<(), Error> {
+            match command {
+                Command::Set { value } => {
+                    *self = serde_json::from_value(value.clone())?;
+                    Ok(())
+                },
+                _ => Err(Error::IncompatibleCommand),
+            }
+        }
+    }
+
+    #[test]
+    fn batches() {
+        let mut hero = Hero { inventory: vec!["sword".into()], equipment: vec![] };
+        let equip = "batch:[\"inventory/remove:0\", \"equipment/push:\\\"sword\\\"\"]";
+
+        match hero.command_str((), &equip.replace("]", ", \"level/set:2\"]")) {
+            Err(Error::PathError) => (),
+            other => panic!("expected a path error, got {:?}", other),
+        }
+        assert_eq!(hero, Hero { inventory: vec!["sword".into()], equipment: vec![] });
+
+        hero.command_str((), equip).unwrap();
+        assert_eq!(hero, Hero { inventory: vec![], equipment: vec!["sword".into()] });
+
+        let mut labeled = Labeled { label: "a".to_string(), value: vec![1u8] };
+        assert!(labeled.command_str((), "batch:[\"value/push:2\",\"label/pop:\"]").is_err());
+        labeled.command_str((), "batch:[\"value/push:2\",\"label/set:\\\"b\\\"\"]").unwrap();
+        assert_eq!(labeled, Labeled { label: "b".to_string(), value: vec![1, 2] });
+
+        let mut tally = Tally(0);
+        tally.command_str((), "set:3").unwrap();
+        assert_eq!(tally.0, 3);
+        assert_eq!(Hero::cmd().batch(vec![
+            Hero::cmd().inventory().remove(0),
+            Hero::cmd().equipment().push(&"sword".to_string()).unwrap(),
+        ]).to_string(), equip.replace(", ", ","));
+
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(Wallet { gold: 10 }, accept);
+        let (a, b) = pipe();
+        let (c, d) = pipe();
+        listener.send(a).unwrap();
+        listener.send(c).unwrap();
+        server.update();
+
+        let mut client = Client::<Wallet, _>::new(b).wait().unwrap();
+        let observer_inbox = d.inbox.clone();
+        let mut observer = Client::<Wallet, _>::new(d).wait().unwrap();
+
+        // the first spend is rolled back and never broadcast
+        let broke = client.request("batch:[\"call:spend:4\", \"call:spend_checked:7\"]");
+        server.update();
+        client.update();
+        assert!(broke.wait().is_err());
+        assert!(observer_inbox.borrow().is_empty());
+        assert_eq!(server.gold, 10);
+
+        let spend = client.request("batch:[\"call:spend:1\", \"call:spend:2\"]");
+        server.update();
+        client.update();
+        assert_eq!(spend.wait().unwrap(), Value::from(7));
+        assert_eq!(*observer_inbox.borrow(), vec!["batch:[\"gold/set:9\",\"gold/set:7\"]".to_string()]);
+        observer.update();
+        assert_eq!(client.gold, 7);
+        assert_eq!(observer.gold, 7);
+
+        // changes next to calls are sent along with the changes the calls make
+        client.command("batch:[\"gold/set:20\", \"call:spend:1\"]").unwrap();
+        server.update();
+        assert_eq!(*observer_inbox.borrow(), vec!["batch:[\"gold/set:20\",\"gold/set:19\"]".to_string()]);
+        client.update();
+        observer.update();
+        assert_eq!((server.gold, client.gold, observer.gold), (19, 19, 19));
+
+        // rolling back only sets the changed parts again, so hidden values survive
+        let mut session = Session { score: 0, socket: Hidden::new("tcp".into()) };
+        assert!(session.command_str((), "batch:[\"score/set:1\", \"nope/set:1\"]").is_err());
+        assert!(session.command_str((), "{score,nope}/set:1").is_err());
+        assert_eq!(session.score, 0);
+        assert_eq!(session.socket.as_str(), "tcp");
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Session {
+        score: u32,
+        socket: Hidden<String>,
+    }
+
+    #[test]
+    fn compare_and_set() {
+        let mut position = Position { x: 1, y: 2 };
+        position.command((), &Position::cmd().x().cas(&1, &5).unwrap()).unwrap();
+        assert_eq!(position.x, 5);
+        match position.command_str((), "cas:{\"x\":1,\"y\":2} {\"x\":0,\"y\":0}") {
+            Err(Error::Conflict(current)) => assert_eq!(current, serde_json::json!({"x": 5, "y": 2})),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(position, Position { x: 5, y: 2 });
+
+        let mut speed = 1.0f64;
+        speed.command_str((), "cas:1 2.5").unwrap();
+        assert_eq!(speed, 2.5);
+
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(Wallet { gold: 10 }, accept);
+        let (a, b) = pipe();
+        listener.send(a).unwrap();
+        server.update();
+        let mut client = Client::<Wallet, _>::new(b).wait().unwrap();
+
+        let first = client.request("gold/cas:10 5");
+        let second = client.request("gold/cas:10 3");
+        server.update();
+        client.update();
+        assert_eq!(first.wait().unwrap(), Value::Null);
+        match second.wait() {
+            Err(Error::Conflict(current)) => assert_eq!(current, Value::from(5)),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(server.gold, 5);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut position = Position { x: 1, y: 2 };
+        position.command((), &Position::cmd().x().add(4).unwrap()).unwrap();
+        position.command((), &Position::cmd().x().mul(-2).unwrap()).unwrap();
+        position.command((), &Position::cmd().y().max(&7).unwrap()).unwrap();
+        position.command_str((), "y/min:5").unwrap();
+        assert_eq!(position, Position { x: -10, y: 5 });
+
+        let mut gold = 3u8;
+        gold.command_str((), "add:-3").unwrap();
+        assert_eq!(gold, 0);
+        match gold.command_str((), "add:-1") {
+            Err(Error::Overflow) => (),
+            other => panic!("expected an overflow, got {:?}", other),
+        }
+        gold.command_str((), "add:255").unwrap();
+        assert!(matches!(gold.command_str((), "mul:2"), Err(Error::Overflow)));
+        assert_eq!(gold, 255);
+
+        let mut speed = 1.5f32;
+        speed.command_str((), "mul:2").unwrap();
+        speed.command_str((), "add:0.5").unwrap();
+        assert_eq!(speed, 3.5);
+        assert!(matches!(speed.command_str((), "mul:1e300"), Err(Error::Overflow)));
+
+        let mut name = "bram".to_string();
+        assert!(matches!(name.command_str((), "add:1"), Err(Error::IncompatibleCommand)));
+    }
+
+    #[test]
+    fn lists() {
+        let mut list = vec![1, 2, 3];
+        list.command_str((), "insert:3 4").unwrap();
+        list.command_str((), "insert:0 0").unwrap();
+        list.command((), &Vec::<i32>::cmd().swap(0, 4)).unwrap();
+        assert_eq!(list, vec![4, 1, 2, 3, 0]);
+        list.command_str((), "move:4 1").unwrap();
+        list.command((), &Vec::<i32>::cmd().move_element(0, 2)).unwrap();
+        assert_eq!(list, vec![0, 1, 4, 2, 3]);
+        list.command((), &Vec::<i32>::cmd().extend(&[5, 6]).unwrap()).unwrap();
+        list.command_str((), "truncate:6").unwrap();
+        assert_eq!(list, vec![0, 1, 4, 2, 3, 5]);
+
+        for command in &["insert:7 1", "remove:6", "swap:0 6", "move:6 0", "move:0 6"] {
+            match list.command_str((), command) {
+                Err(Error::OutOfRange) => (),
+                other => panic!("expected {} to be out of range, got {:?}", command, other),
+            }
+        }
+        list.command_str((), "clear:").unwrap();
+        assert!(matches!(list.command_str((), "remove:0"), Err(Error::OutOfRange)));
+
+        let mut deque: VecDeque<i32> = (0..5).collect();
+        deque.command_str((), "move:0 4").unwrap();
+        deque.command_str((), "insert:1 9").unwrap();
+        deque.command_str((), "remove:0").unwrap();
+        assert_eq!(deque, vec![9, 2, 3, 4, 0]);
+
+        let mut array = [1, 2, 3];
+        array.command_str((), "move:2 0").unwrap();
+        array.command_str((), "swap:1 2").unwrap();
+        assert_eq!(array, [3, 2, 1]);
+        assert!(matches!(array.command_str((), "swap:1 3"), Err(Error::OutOfRange)));
+        assert!(matches!(array.command_str((), "clear:"), Err(Error::IncompatibleCommand)));
+    }
+
+    #[test]
+    fn queries() {
+        let mut hero = Hero { inventory: vec!["sword".into(), "shield".into()], equipment: vec![] };
+        let reply = crate::reply::Reply::new(Vec::new());
+        hero.command_str(reply.clone(), "inventory/1/get:").unwrap();
+        assert_eq!(reply.take_response(), Some(Value::from("shield")));
+        hero.command(reply.clone(), &Hero::cmd().get()).unwrap();
+        assert_eq!(reply.take_response(), Some(serde_json::to_value(&hero).unwrap()));
+        assert!(!Command::parse("a/b/get:").unwrap().is_mutation());
+
+        let mut attributes = Attributes { health: 10, level: 3, cache: Default::default() };
+        attributes.command_str(reply.clone(), "level/get:").unwrap();
+        assert_eq!(reply.take_response(), Some(Value::from(3)));
+        assert!(reply.into_inner().is_empty());
+
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(hero, accept);
+        let (a, b) = pipe();
+        let (c, d) = pipe();
+        listener.send(a).unwrap();
+        listener.send(c).unwrap();
+        server.update();
+        let mut client = Client::<Hero, _>::new(b).wait().unwrap();
+        let observer_inbox = d.inbox.clone();
+        let _observer = Client::<Hero, _>::new(d).wait().unwrap();
+
+        let inventory = client.request("inventory/get:");
+        let missing = client.request("inventory/2/get:");
+        server.update();
+        client.update();
+        assert_eq!(inventory.wait().unwrap(), serde_json::json!(["sword", "shield"]));
+        assert!(missing.wait().is_err());
+        assert!(observer_inbox.borrow().is_empty());
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Seat {
+        pub(crate) ready: bool,
+        pub(crate) position: Position,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Lobby {
+        pub(crate) seats: Vec<Seat>,
+        pub(crate) scores: HashMap<String, u32>,
+    }
+
+    #[test]
+    fn wildcards() {
+        let seat = || Seat { ready: true, position: Position { x: 1, y: 2 } };
+        let mut lobby = Lobby {
+            seats: vec![seat(), seat()],
+            scores: vec![("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 3)].into_iter().collect(),
+        };
+
+        lobby.command_str((), "seats/*/ready/set:false").unwrap();
+        lobby.command_str((), "seats/1/position/*/set:0").unwrap();
+        lobby.command_str((), "scores/{\"a\",\"c\"}/add:10").unwrap();
+        assert!(lobby.seats.iter().all(|seat| !seat.ready));
+        assert_eq!(lobby.seats[0].position, Position { x: 1, y: 2 });
+        assert_eq!(lobby.seats[1].position, Position { x: 0, y: 0 });
+        assert_eq!(lobby.scores["a"], 11);
+        assert_eq!(lobby.scores["b"], 2);
+        assert_eq!(lobby.scores["c"], 13);
+
+        // fanning out is atomic
+        assert!(lobby.command_str((), "seats/{0,2}/ready/set:true").is_err());
+        assert!(!lobby.seats[0].ready);
+
+        let mut attributes = Attributes { health: 10, level: 1, cache: Default::default() };
+        attributes.command_str((), "*/set:5").unwrap();
+        assert_eq!((attributes.health, attributes.level), (5, 1));
+        assert!(matches!(attributes.command_str((), "{hp,level}/set:6"), Err(Error::ReadOnly)));
+        assert_eq!(attributes.health, 5);
+
+        let mut marker = Marker { size: 1, position: Position { x: 1, y: 2 } };
+        marker.command_str((), "*/set:5").unwrap();
+        assert_eq!((marker.size, marker.position.x, marker.position.y), (5, 5, 5));
+
+        let command = Command::parse("{a\\,b,\"c\"}/*/pop:").unwrap();
+        assert_eq!(command, Command::Each {
+            elements: Some(vec!["a,b".into(), "\"c\"".into()]),
+            command: Box::new(Command::Each { elements: None, command: Box::new(Command::Pop) }),
+        });
+        assert_eq!(Command::parse(&command.to_string()).unwrap(), command);
+        assert_eq!(Command::parse("\\*/pop:").unwrap().to_string(), "\\*/pop:");
+
+        register::<Sword>("Sword");
+        let mut swords: Vec<Box<dyn DynReflect>> = vec![Box::new(Sword { damage: 3 }), Box::new(Sword { damage: 1 })];
+        let reply = crate::reply::Reply::new(Vec::new());
+        swords.command_str(reply.clone(), "*/call:sharpen:1").unwrap();
+        assert_eq!(reply.into_inner(), vec![(
+            "batch:[\"0/damage/set:4\",\"1/damage/set:2\"]".to_string(),
+            crate::reply::Audience::All,
+        )]);
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Marker {
+        size: u32,
+        #[serde(flatten)]
+        position: Position,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Note {
+        text: String,
+    }
+
+    #[test]
+    fn splices() {
+        let mut text = "héllo wörld".to_string();
+        text.command_str((), "splice:1 4 \"ey\"").unwrap();
+        assert_eq!(text, "hey wörld");
+        text.command((), &String::cmd().splice(9, 0, "!")).unwrap();
+        assert_eq!(text, "hey wörld!");
+        assert!(matches!(text.command_str((), "splice:11 0 \"?\""), Err(Error::OutOfRange)));
+        assert!(matches!(text.command_str((), "splice:8 3 \"\""), Err(Error::OutOfRange)));
+        assert_eq!(text, "hey wörld!");
+
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(Note { text: "hello".into() }, accept);
+        let (a, b) = pipe();
+        let (c, d) = pipe();
+        listener.send(a).unwrap();
+        listener.send(c).unwrap();
+        server.update();
+        let mut first = Client::<Note, _>::new(b).wait().unwrap();
+        let mut second = Client::<Note, _>::new(d).wait().unwrap();
+
+        // both clients edit "hello" before they see each other's splices
+        first.command("text/splice:0 0 \"oh, \"").unwrap();
+        first.command("text/splice:4 0 \"no \"").unwrap();
+        second.command("text/splice:5 0 \" world\"").unwrap();
+        server.update();
+        first.update();
+        second.update();
+        assert_eq!(server.text, "oh, no hello world");
+        assert_eq!(first.text, server.text);
+        assert_eq!(second.text, server.text);
+
+        second.command("text/splice:0 2 \"ah\"").unwrap();
+        server.command("text/splice:15 3 \"\"").unwrap();
+        server.update();
+        first.update();
+        second.update();
+        assert_eq!(server.text, "ah, no hello wo");
+        assert_eq!(first.text, server.text);
+        assert_eq!(second.text, server.text);
+
+        let moved = transform(&Command::parse("text/splice:3 4 \"x\"").unwrap(),
+                              &Command::parse("text/splice:1 4 \"\"").unwrap());
+        assert_eq!(moved.to_string(), "text/splice:1 2 \"x\"");
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Sword {
+        pub(crate) damage: u32,
+    }
+
+    #[reflect_methods]
+    impl Sword {
+        fn sharpen<C: Context>(&mut self, mut context: C, amount: u32) {
+            let damage = self.damage + amount;
+            context.command(self, format!("damage/set:{}", damage)).unwrap();
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Counter {
+        count: i64,
+        log: Vec<String>,
+    }
+
+    #[reflect_methods]
+    impl Counter {
+        fn add<C: Context>(&mut self, _context: C, amount: i64) {
+            self.count += amount;
+        }
+
+        fn log(&mut self, _context: impl Context, message: String, times: usize) {
+            for _ in 0..times {
+                self.log.push(message.clone());
+            }
+        }
+
+        fn take<C: Context>(&mut self, _context: C, amount: i64) -> Result<i64, String> {
+            if amount > self.count {
+                return Err(format!("can't take {} from {}", amount, self.count));
+            }
+            self.count -= amount;
+            Ok(self.count)
+        }
+
+        fn reset(&mut self) {
+            self.count = 0;
+        }
+    }
+
+    #[test]
+    fn reflect_methods() {
+        let mut test = Counter { count: 0, log: vec![] };
+        test.command_str((), "call:add:5").unwrap();
+        test.command_str((), "call:log:\"hi\" 2").unwrap();
+        assert_eq!(test.count, 5);
+        assert_eq!(test.log, vec!["hi".to_string(), "hi".to_string()]);
+
+        match test.command_str((), "call:add:5 6") {
+            Err(Error::WrongArgumentCount) => (),
+            other => panic!("expected a wrong argument count, got {:?}", other),
+        }
+        match test.command_str((), "call:log:\"hi\"") {
+            Err(Error::WrongArgumentCount) => (),
+            other => panic!("expected a wrong argument count, got {:?}", other),
+        }
+        test.command_str((), "call:take:2").unwrap();
+        assert_eq!(test.count, 3);
+        match test.command_str((), "call:take:4") {
+            Err(Error::FunctionError(ref message)) if message == "can't take 4 from 3" => (),
+            other => panic!("expected a function error, got {:?}", other),
+        }
+
+        match test.command_str((), "call:reset:") {
+            Err(Error::InvalidCommand) => (),
+            other => panic!("expected an invalid command, got {:?}", other),
+        }
+        test.reset();
+        assert_eq!(test.count, 0);
+
+        let mut test = Marked { value: 0 };
+        test.command_str((), "call:exported:3").unwrap();
+        assert_eq!(test.value, 3);
+        match test.command_str((), "call:hidden:3") {
+            Err(Error::InvalidCommand) => (),
+            other => panic!("expected an invalid command, got {:?}", other),
+        }
+        test.hidden((), 1);
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Marked {
+        value: u32,
+    }
+
+    #[reflect_methods]
+    impl Marked {
+        #[reflect_fn]
+        fn exported<C: Context>(&mut self, _context: C, value: u32) {
+            self.value = value;
+        }
+
+        fn hidden<C: Context>(&mut self, _context: C, value: u32) {
+            self.value = value;
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Player {
+        player_name: String,
+        #[serde(rename = "hp")]
+        health: u32,
+        #[serde(flatten)]
+        position: Position,
+        state: PlayerState,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Position {
+        pub(crate) x: i32,
+        pub(crate) y: i32,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum PlayerState {
+        #[serde(rename_all = "SCREAMING-KEBAB-CASE")]
+        InGame { team_id: u32 },
+        InLobby,
+    }
+
+    #[test]
+    fn serde_names() {
+        let mut test = Player {
+            player_name: "bram".into(),
+            health: 10,
+            position: Position { x: 0, y: 0 },
+            state: PlayerState::InGame { team_id: 0 },
+        };
+        test.command_str((), "playerName/set:\"kurble\"").unwrap();
+        test.command_str((), "hp/set:5").unwrap();
+        test.command_str((), "y/set:3").unwrap();
+        test.command_str((), "state/in_game/TEAM-ID/set:2").unwrap();
+
+        let json = serde_json::to_value(&test).unwrap();
+        assert_eq!(json["playerName"], "kurble");
+        assert_eq!(json["hp"], 5);
+        assert_eq!(json["y"], 3);
+        assert_eq!(json["state"]["in_game"]["TEAM-ID"], 2);
+
+        match test.command_str((), "player_name/set:\"bram\"") {
+            Err(Error::PathError) => (),
+            other => panic!("expected a path error, got {:?}", other),
+        }
+        match test.command_str((), "z/set:1") {
+            Err(Error::PathError) => (),
+            other => panic!("expected a path error, got {:?}", other),
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Secrets {
+        shown: u32,
+        #[serde(skip)]
+        secret: u32,
+        #[serde(skip_serializing, default)]
+        written: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Stroke {
+        depth: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    #[serde(tag = "kind")]
+    pub enum Gait {
+        Walk { speed: u32 },
+        Swim(Stroke),
+        Stand,
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    #[serde(tag = "t", content = "c")]
+    pub enum Message {
+        Text(String),
+        Move { x: i32 },
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    #[serde(untagged)]
+    pub enum Setting {
+        Flag { on: bool },
+        Range(u32, u32),
+    }
+
+    #[test]
+    fn serde_shapes() {
+        let mut test = Secrets { shown: 1, secret: 2, written: 3 };
+        test.command_str((), "shown/set:4").unwrap();
+        assert!(matches!(test.command_str((), "secret/set:5"), Err(Error::PathError)));
+        assert!(matches!(test.command_str((), "written/set:5"), Err(Error::PathError)));
+        assert_eq!(test, Secrets { shown: 4, secret: 2, written: 3 });
+
+        // internally tagged and untagged enums have the fields of a variant at their own level
+        let mut test = Gait::Walk { speed: 1 };
+        test.command_str((), "speed/set:2").unwrap();
+        assert_eq!(serde_json::to_value(&test).unwrap(), serde_json::json!({ "kind": "Walk", "speed": 2 }));
+        assert!(matches!(test.command_str((), "Walk/speed/set:3"), Err(Error::PathError)));
+        assert_eq!(Gait::cmd().walk().speed().set(&3).unwrap().to_string(), "speed/set:3");
+        test.command_str((), "set:{\"kind\":\"Swim\",\"depth\":1}").unwrap();
+        test.command((), &Gait::cmd().swim()._0().depth().set(&5).unwrap()).unwrap();
+        assert_eq!(test, Gait::Swim(Stroke { depth: 5 }));
+        test.command_str((), "set:{\"kind\":\"Stand\"}").unwrap();
+        assert!(matches!(test.command_str((), "depth/set:1"), Err(Error::PathError)));
+
+        let mut test = Setting::Range(1, 2);
+        test.command_str((), "1/set:5").unwrap();
+        assert_eq!(test, Setting::Range(1, 5));
+        test.command_str((), "set:{\"on\":false}").unwrap();
+        test.command_str((), "on/set:true").unwrap();
+        assert_eq!(test, Setting::Flag { on: true });
+
+        // adjacently tagged enums have them in their content
+        let mut test = Message::Move { x: 1 };
+        test.command_str((), "c/x/set:2").unwrap();
+        assert_eq!(serde_json::to_value(&test).unwrap(), serde_json::json!({ "t": "Move", "c": { "x": 2 } }));
+        assert_eq!(Message::cmd().text()._0().set(&"hi".to_string()).unwrap().to_string(), "c/0/set:\"hi\"");
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub enum Shape {
+        Empty,
+        Circle(f32),
+        Rect { width: f32, tags: Vec<String> },
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub enum Direction {
+        Left,
+        Right,
+    }
+
+    #[test]
+    fn enums() {
+        let mut test = Shape::Rect { width: 1.0, tags: vec![] };
+        test.command_str((), "Rect/width/set:2.5").unwrap();
+        test.command_str((), "Rect/tags/push:\"big\"").unwrap();
+        assert_eq!(test, Shape::Rect { width: 2.5, tags: vec!["big".into()] });
+
+        match test.command_str((), "Circle/0/set:1.0") {
+            Err(Error::PathError) => (),
+            other => panic!("expected a path error, got {:?}", other),
+        }
+        match test.command_str((), "Rect/height/set:1.0") {
+            Err(Error::PathError) => (),
+            other => panic!("expected a path error, got {:?}", other),
+        }
+
+        test.command_str((), "set:{\"Circle\":3.0}").unwrap();
+        test.command_str((), "Circle/0/set:4.0").unwrap();
+        assert_eq!(test, Shape::Circle(4.0));
+
+        test.command_str((), "set:\"Empty\"").unwrap();
+        assert_eq!(test, Shape::Empty);
+
+        let mut test = Direction::Left;
+        test.command_str((), "set:\"Right\"").unwrap();
+        assert_eq!(test, Direction::Right);
+    }
+
+    #[test]
+    fn diffs() {
+        let seat = |ready| Seat { ready, position: Position { x: 1, y: 2 } };
+        let old = Lobby {
+            seats: vec![seat(true), seat(true), seat(false)],
+            scores: vec![("a".to_string(), 1), ("b".to_string(), 2)].into_iter().collect(),
+        };
+        let mut new = Lobby {
+            seats: vec![seat(true), seat(false), seat(true), seat(false)],
+            scores: vec![("a".to_string(), 5), ("c".to_string(), 3)].into_iter().collect(),
+        };
+        new.seats[0].position.x = 4;
+
+        let mut commands: Vec<String> = diff(&old, &new).unwrap().iter().map(Command::to_string).collect();
+        commands.sort();
+        assert_eq!(commands, vec![
+            "scores/\"a\"/set:5", "scores/insert:\"c\" 3", "scores/remove:\"b\"",
+            "seats/0/position/x/set:4", "seats/insert:1 {\"position\":{\"x\":1,\"y\":2},\"ready\":false}",
+        ]);
+
+        let mut synced = old;
+        for command in diff(&synced, &new).unwrap() {
+            synced.command((), &command).unwrap();
+        }
+        assert_eq!(synced, new);
+        assert!(diff(&synced, &new).unwrap().is_empty());
+
+        let shrink = |old: Vec<u8>, new: Vec<u8>| diff(&old, &new).unwrap().iter().map(Command::to_string).collect::<Vec<_>>();
+        assert_eq!(shrink(vec![1, 2, 3], vec![1]), vec!["pop:", "pop:"]);
+        assert_eq!(shrink(vec![1, 2, 3], vec![3]), vec!["remove:0", "remove:0"]);
+        assert_eq!(shrink(vec![1, 2], vec![1, 2, 3]), vec!["push:3"]);
+
+        assert_eq!(diff(&Some(seat(true)), &Some(seat(false))).unwrap()[0].to_string(), "val/ready/set:false");
+        assert_eq!(diff(&Some(1), &None).unwrap()[0].to_string(), "remove:null");
+        assert_eq!(diff(&[1, 2], &[1, 3]).unwrap()[0].to_string(), "1/set:3");
+
+        // read-only fields can't be changed through their path
+        let old = Attributes { health: 10, level: 1, cache: Default::default() };
+        let new = Attributes { health: 12, level: 2, cache: Default::default() };
+        assert_eq!(diff(&old, &new).unwrap()[0].to_string(), "set:{\"health\":12,\"level\":2}");
+        let new = Attributes { health: 12, level: 1, cache: Default::default() };
+        assert_eq!(diff(&old, &new).unwrap()[0].to_string(), "hp/set:12");
+    }
+
+    #[derive(Serialize, Deserialize, Reflect, Debug, PartialEq)]
+    pub struct Tree {
+        pub(crate) value: char,
+        pub(crate) next: Option<Box<Tree>>,
+        pub(crate) tags: BTreeSet<String>,
+    }
+
+    #[test]
+    fn collections() {
+        let mut ranking: std::collections::BTreeMap<u32, String> = Default::default();
+        ranking.command_str((), "insert:2 \"b\"").unwrap();
+        ranking.command_str((), "insert:1 \"a\"").unwrap();
+        ranking.command_str((), "2/splice:1 0 \"!\"").unwrap();
+        ranking.command_str((), "/1/set:\"c\"").unwrap();
+        assert_eq!(serde_json::to_string(&ranking).unwrap(), "{\"1\":\"c\",\"2\":\"b!\"}");
+        ranking.command_str((), "remove:1").unwrap();
+        assert_eq!(ranking.len(), 1);
+
+        let mut tags: HashSet<String> = HashSet::new();
+        tags.command_str((), "insert:\"a\"").unwrap();
+        tags.command_str((), "extend:[\"b\", \"c\"]").unwrap();
+        tags.command_str((), "remove:\"b\"").unwrap();
+        tags.command((), &HashSet::<String>::cmd().insert(&"a".to_string()).unwrap()).unwrap();
+        assert_eq!(tags, vec!["a".to_string(), "c".to_string()].into_iter().collect());
+        assert!(tags.command_str((), "0/set:\"d\"").is_err());
+        assert_eq!(Command::parse("insert:\"a\"").unwrap().to_string(), "insert:\"a\"");
+        assert!(Command::parse("insert:").is_err());
+
+        let leaf = |value| Box::new(Tree { value, next: None, tags: BTreeSet::new() });
+        let mut tree = Tree { value: 'a', next: Some(leaf('b')), tags: BTreeSet::new() };
+        tree.command_str((), "next/val/value/set:\"c\"").unwrap();
+        tree.command_str((), "next/val/next/set:{\"value\":\"d\",\"next\":null,\"tags\":[\"x\"]}").unwrap();
+        tree.command_str((), "next/val/next/val/tags/insert:\"y\"").unwrap();
+        assert_eq!(tree.next.as_ref().unwrap().value, 'c');
+        assert_eq!(tree.next.as_ref().unwrap().next.as_ref().unwrap().tags.len(), 2);
+        assert!(tree.command_str((), "value/set:\"too long\"").is_err());
+
+        let mut new = Tree { value: 'a', next: Some(leaf('c')), tags: BTreeSet::new() };
+        let mut last = leaf('d');
+        last.tags.insert("z".to_string());
+        new.next.as_mut().unwrap().next = Some(last);
+        let commands: Vec<String> = diff(&tree, &new).unwrap().iter().map(Command::to_string).collect();
+        assert_eq!(commands, vec!["next/val/next/val/tags/remove:\"x\"", "next/val/next/val/tags/remove:\"y\"",
+                                  "next/val/next/val/tags/insert:\"z\""]);
+
+        let mut boxed = Box::new(5u8);
+        boxed.command_str((), "add:-2").unwrap();
+        assert_eq!(*boxed, 3);
+        let mut boxed: Box<Option<Vec<u8>>> = Box::new(Some(vec![1]));
+        boxed.command_str((), "val/push:2").unwrap();
+        assert_eq!(*boxed, Some(vec![1, 2]));
+        let mut boxed: Box<(u8, [u8; 2], Value)> = Box::new((1, [2, 3], Value::Null));
+        boxed.command_str((), "0/add:1").unwrap();
+        boxed.command_str((), "1/swap:0 1").unwrap();
+        boxed.command((), &<Box<(u8, [u8; 2], Value)>>::cmd().inner()._2().set(&"x".into()).unwrap()).unwrap();
+        assert_eq!(*boxed, (2, [3, 2], Value::from("x")));
+
+        let mut big = u128::from(u64::MAX);
+        big.command_str((), "add:-1").unwrap();
+        big.command_str((), "mul:2").unwrap();
+        assert_eq!(big, (u128::from(u64::MAX) - 1) * 2);
+        assert!(matches!(big.command_str((), "mul:-1"), Err(Error::Overflow)));
+        let mut signed = -5i128;
+        signed.command_str((), "add:-3").unwrap();
+        assert_eq!(signed, -8);
+
+        // numbers beyond 64 bits keep their precision in commands, replies and rollbacks
+        let mut big = 0u128;
+        big.command((), &u128::cmd().set(&(u128::MAX - 1)).unwrap()).unwrap();
+        big.command((), &u128::cmd().add(1).unwrap()).unwrap();
+        assert_eq!(big, u128::MAX);
+        big.command_str((), "batch:[\"add:-1\",\"add:-1\"]").unwrap();
+        assert!(big.command_str((), "batch:[\"add:-1\",\"pop:\"]").is_err());
+        assert_eq!(big, u128::MAX - 2);
+        let reply = crate::reply::Reply::new(Vec::new());
+        big.command(reply.clone(), &Command::Get).unwrap();
+        assert_eq!(reply.take_response().unwrap().to_string(), (u128::MAX - 2).to_string());
+        big.command((), &u128::cmd().cas(&(u128::MAX - 2), &u128::MAX).unwrap()).unwrap();
+        assert!(matches!(big.command_str((), "cas:0 1"), Err(Error::Conflict(_))));
+        assert_eq!(diff(&0u128, &big).unwrap()[0].to_string(), format!("set:{}", u128::MAX));
+        let mut history = UndoStack::new();
+        let command = Command::parse("set:1").unwrap();
+        history.record(&mut big, &command).unwrap();
+        big.command((), &command).unwrap();
+        big.command((), &history.undo().unwrap()).unwrap();
+        assert_eq!(big, u128::MAX);
+        let mut signed = 0i128;
+        signed.command((), &i128::cmd().add(i128::MAX).unwrap()).unwrap();
+        signed.command_str((), &format!("max:{}", i128::MIN)).unwrap();
+        assert_eq!(signed, i128::MAX);
+
+        let mut unit = ();
+        unit.command_str((), "set:null").unwrap();
+        assert!(unit.command_str((), "set:1").is_err());
+    }
+
+    #[test]
+    fn tuples() {
+        let mut pair: (Vec<u32>, HashMap<String, u32>) = (vec![1], HashMap::new());
+        pair.command_str((), "0/push:2").unwrap();
+        pair.command_str((), "1/insert:\"a\" 3").unwrap();
+        pair.command_str((), "1/\"a\"/add:1").unwrap();
+        assert_eq!(pair.0, vec![1, 2]);
+        assert_eq!(pair.1["a"], 4);
+        assert!(matches!(pair.command_str((), "2/set:1"), Err(Error::PathError)));
+
+        let mut point = (1i32, 2i32, "a".to_string());
+        point.command_str((), "{0,1}/add:10").unwrap();
+        point.command_str((), "2/splice:1 0 \"b\"").unwrap();
+        point.command_str((), "set:[0, 0, \"c\"]").unwrap();
+        point.command((), &<(i32, i32, String)>::cmd()._1().add(5).unwrap()).unwrap();
+        assert_eq!(point, (0, 5, "c".to_string()));
+        assert!(point.command_str((), "*/add:1").is_err());
+        assert_eq!(point, (0, 5, "c".to_string()));
+
+        let commands: Vec<String> = diff(&point, &(0, 6, "c".to_string())).unwrap().iter().map(Command::to_string).collect();
+        assert_eq!(commands, vec!["1/set:6"]);
+    }
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Plugin {
+        name: String,
+        settings: Value,
+    }
+
+    #[test]
+    fn json_values() {
+        let mut plugin = Plugin { name: "map".into(), settings: serde_json::json!({ "zoom": 1, "layers": ["roads"] }) };
+        plugin.command_str((), "settings/zoom/add:2").unwrap();
+        plugin.command_str((), "settings/zoom/mul:0.5").unwrap();
+        plugin.command_str((), "settings/layers/push:\"rivers\"").unwrap();
+        plugin.command_str((), "settings/layers/insert:0 \"towns\"").unwrap();
+        plugin.command_str((), "settings/insert:\"theme\" {\"dark\":false}").unwrap();
+        plugin.command_str((), "settings/\"theme\"/dark/set:true").unwrap();
+        plugin.command_str((), "/settings/theme/insert:\"font\" \"serif\"").unwrap();
+        plugin.command_str((), "settings/theme/font/splice:0 1 \"S\"").unwrap();
+        plugin.command((), &Plugin::cmd().settings().key("layers").at(1).set(&"highways".into()).unwrap()).unwrap();
+        assert_eq!(plugin.settings, serde_json::json!({
+            "zoom": 1.5,
+            "layers": ["towns", "highways", "rivers"],
+            "theme": { "dark": true, "font": "Serif" },
+        }));
+
+        plugin.command_str((), "settings/layers/remove:0").unwrap();
+        plugin.command_str((), "settings/layers/pop:").unwrap();
+        plugin.command_str((), "settings/remove:\"zoom\"").unwrap();
+        plugin.command_str((), "settings/theme/*/set:0").unwrap();
+        assert_eq!(plugin.settings, serde_json::json!({ "layers": ["highways"], "theme": { "dark": 0, "font": 0 } }));
+
+        assert!(matches!(plugin.command_str((), "settings/missing/set:1"), Err(Error::PathError)));
+        assert!(matches!(plugin.command_str((), "settings/theme/dark/push:1"), Err(Error::IncompatibleCommand)));
+        assert!(matches!(plugin.command_str((), "settings/layers/3/set:1"), Err(Error::PathError)));
+
+        let mut counter = Value::from(u64::MAX - 1);
+        counter.command_str((), "add:1").unwrap();
+        assert_eq!(counter, Value::from(u64::MAX));
+        assert!(matches!(counter.command_str((), "add:1"), Err(Error::Overflow)));
+
+        let new = serde_json::json!({ "layers": ["highways", "trails"], "theme": { "dark": 1, "font": 0 }, "beta": true });
+        let commands: Vec<String> = diff(&plugin.settings, &new).unwrap().iter().map(Command::to_string).collect();
+        assert_eq!(commands, vec!["\"layers\"/push:\"trails\"", "\"theme\"/\"dark\"/set:1", "insert:\"beta\" true"]);
+        for command in diff(&plugin.settings, &new).unwrap() {
+            plugin.settings.command((), &command).unwrap();
+        }
+        assert_eq!(plugin.settings, new);
+    }
 }
@@ -1,11 +1,46 @@
 use super::*;
+use serde::de::Error as _;
+use serde::ser::SerializeTuple;
 use serde_json::from_value;
 use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::ops::{Deref, DerefMut};
 
-impl<T: Reflect> Reflect for Vec<T> {
+/// Swap two elements of a list, failing instead of panicking on invalid indices.
+pub(crate) fn swap<T>(list: &mut [T], a: usize, b: usize) -> Result<(), Error> {
+    if a >= list.len() || b >= list.len() {
+        return Err(Error::OutOfRange);
+    }
+    list.swap(a, b);
+    Ok(())
+}
+
+/// Move the element at `from` to `to`, shifting the elements in between.
+pub(crate) fn move_element<T>(list: &mut [T], from: usize, to: usize) -> Result<(), Error> {
+    if from >= list.len() || to >= list.len() {
+        return Err(Error::OutOfRange);
+    }
+    if from < to {
+        list[from..=to].rotate_left(1);
+    } else {
+        list[to..=from].rotate_right(1);
+    }
+    Ok(())
+}
+
+/// Returns the index deserialized from `key`, if it is below `end`.
+pub(crate) fn index(key: &Value, end: usize) -> Result<usize, Error> {
+    let index: usize = from_value(key.clone())?;
+    if index >= end {
+        return Err(Error::OutOfRange);
+    }
+    Ok(index)
+}
+
+impl<T: Reflect + Serialize> Reflect for Vec<T> {
     fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
         match command {
-            &Command::Path { ref element, ref command } => {
+            Command::Path { element, command } => {
                 let index: usize = element.parse()?;
                 let elem: &mut T = self.get_mut(index).ok_or(Error::PathError)?;
 
@@ -13,31 +48,61 @@ impl<T: Reflect> Reflect for Vec<T> {
                 context.with_inner(element.as_str(), |c| result=elem.command(c, command));
                 result
             },
-            &Command::Set { ref value } => {
+            Command::Each { elements, command } => {
+                apply_each(self, context, elements, |list| Ok((0..list.len()).map(|i| i.to_string()).collect()), command)
+            },
+            Command::Set { value } => {
                 *self = from_value(value.clone())?;
                 Ok(())
             },
-            &Command::Push { ref value } => {
+            Command::Push { value } => {
                 self.push(from_value(value.clone())?);
                 Ok(())
             },
-            &Command::Pop => {
+            Command::Pop => {
                 self.pop();
                 Ok(())
             },
-            &Command::Remove { ref key } => {
-                self.remove(from_value(key.clone())?);
+            Command::Insert { key, value } => {
+                let index = index(key, self.len() + 1)?;
+                self.insert(index, from_value(value.clone())?);
+                Ok(())
+            },
+            Command::Remove { key } => {
+                let index = index(key, self.len())?;
+                self.remove(index);
                 Ok(())
             }
-            &_ => Err(Error::IncompatibleCommand),
+            Command::Swap { a, b } => swap(self, *a, *b),
+            Command::Move { from, to } => move_element(self, *from, *to),
+            Command::Truncate { len } => {
+                self.truncate(*len);
+                Ok(())
+            },
+            Command::Clear => {
+                self.clear();
+                Ok(())
+            },
+            Command::Extend { value } => {
+                self.extend(from_value::<Vec<T>>(value.clone())?);
+                Ok(())
+            },
+            Command::Get => apply_get(self, context),
+            Command::Cas { expected, value } => apply_cas(self, &context, expected, value),
+            Command::Batch { commands } => apply_batch(self, context, commands),
+            _ => Err(Error::IncompatibleCommand),
         }
     }
+
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+        diff_list(&self.iter().collect::<Vec<_>>(), &new.iter().collect::<Vec<_>>())
+    }
 }
 
-impl<T: Reflect> Reflect for VecDeque<T> {
+impl<T: Reflect + Serialize> Reflect for VecDeque<T> {
     fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
         match command {
-            &Command::Path { ref element, ref command } => {
+            Command::Path { element, command } => {
                 let index: usize = element.parse()?;
                 let elem: &mut T = self.get_mut(index).ok_or(Error::PathError)?;
 
@@ -45,52 +110,215 @@ impl<T: Reflect> Reflect for VecDeque<T> {
                 context.with_inner(element.as_str(), |c| result=elem.command(c, command));
                 result
             },
-            &Command::Set { ref value } => {
+            Command::Each { elements, command } => {
+                apply_each(self, context, elements, |list| Ok((0..list.len()).map(|i| i.to_string()).collect()), command)
+            },
+            Command::Set { value } => {
                 *self = from_value(value.clone())?;
                 Ok(())
             },
-            &Command::Push { ref value } => {
+            Command::Push { value } => {
                 self.push_back(from_value(value.clone())?);
                 Ok(())
             },
-            &Command::Pop => {
+            Command::Pop => {
                 self.pop_back();
                 Ok(())
             },
-            &Command::Remove { ref key } => {
-                self.remove(from_value(key.clone())?);
+            Command::Insert { key, value } => {
+                let index = index(key, self.len() + 1)?;
+                self.insert(index, from_value(value.clone())?);
+                Ok(())
+            },
+            Command::Remove { key } => {
+                let index = index(key, self.len())?;
+                self.remove(index);
                 Ok(())
             }
-            &_ => Err(Error::IncompatibleCommand),
+            Command::Swap { a, b } => swap(self.make_contiguous(), *a, *b),
+            Command::Move { from, to } => move_element(self.make_contiguous(), *from, *to),
+            Command::Truncate { len } => {
+                self.truncate(*len);
+                Ok(())
+            },
+            Command::Clear => {
+                self.clear();
+                Ok(())
+            },
+            Command::Extend { value } => {
+                self.extend(from_value::<Vec<T>>(value.clone())?);
+                Ok(())
+            },
+            Command::Get => apply_get(self, context),
+            Command::Cas { expected, value } => apply_cas(self, &context, expected, value),
+            Command::Batch { commands } => apply_batch(self, context, commands),
+            _ => Err(Error::IncompatibleCommand),
         }
     }
+
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+        diff_list(&self.iter().collect::<Vec<_>>(), &new.iter().collect::<Vec<_>>())
+    }
 }
 
-macro_rules! array {
-    ($($nn:expr,)*) => { $(array!($nn);)* };
-    ($n:expr) => {
-        impl<T: Reflect> Reflect for [T; $n] {
-            fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
-                match command {
-                    &Command::Path { ref element, ref command } => {
-                        let index: usize = element.parse()?;
-                        let elem: &mut T = self.get_mut(index).ok_or(Error::PathError)?;
-                        let mut result = Ok(());
-                        context.with_inner(element.as_str(), |c| {
-                            result = elem.command(c, command).map(|_|());
-                        });
-                        result
-                    },
-                    &Command::Set { ref value } => {
-                        *self = from_value(value.clone())?;
-                        Ok(())
-                    },
-                    &_ => Err(Error::IncompatibleCommand),
-                }
-            }
+/// Execute `command` on a list with a fixed length, which supports the list commands that keep
+///  its length.
+fn command_fixed<R, T, C>(list: &mut R, mut context: C, command: &Command) -> Result<(), Error> where
+    R: Reflect + Serialize + AsRef<[T]> + AsMut<[T]>,
+    T: Reflect,
+    C: Context
+{
+    match command {
+        Command::Path { element, command } => {
+            let index: usize = element.parse()?;
+            let elem: &mut T = list.as_mut().get_mut(index).ok_or(Error::PathError)?;
+            let mut result = Ok(());
+            context.with_inner(element.as_str(), |c| result = elem.command(c, command));
+            result
+        },
+        Command::Each { elements, command } => {
+            apply_each(list, context, elements, |list| Ok((0..list.as_ref().len()).map(|i| i.to_string()).collect()), command)
+        },
+        Command::Set { value } => {
+            *list = from_value(value.clone())?;
+            Ok(())
+        },
+        Command::Swap { a, b } => swap(list.as_mut(), *a, *b),
+        Command::Move { from, to } => move_element(list.as_mut(), *from, *to),
+        Command::Get => apply_get(list, context),
+        Command::Cas { expected, value } => apply_cas(list, &context, expected, value),
+        Command::Batch { commands } => apply_batch(list, context, commands),
+        _ => Err(Error::IncompatibleCommand),
+    }
+}
+
+fn diff_fixed<T: Reflect + Serialize>(old: &[T], new: &[T]) -> Result<Vec<Command>, Error> {
+    let mut commands = Vec::new();
+    for (index, (old, new)) in old.iter().zip(new.iter()).enumerate() {
+        diff_child(&index.to_string(), old, new, &mut commands)?;
+    }
+    Ok(commands)
+}
+
+/// Serde only serializes arrays of up to 32 elements, so longer arrays need `Array`.
+impl<T: Reflect + Serialize, const N: usize> Reflect for [T; N] where [T; N]: Serialize + for<'de> Deserialize<'de> {
+    fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+        command_fixed(self, context, command)
+    }
+
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+        diff_fixed(self, new)
+    }
+}
+
+/// An array of any length, which serializes like a shorter array would.
+/// Use it instead of an array with more than 32 elements, which serde can't serialize, so that
+///  such arrays don't implement `Reflect`:
+///
+/// ```compile_fail
+/// fn mirrored<T: mirror::Reflect>() {}
+/// mirrored::<[u8; 33]>();
+/// ```
+///
+/// ```
+/// fn mirrored<T: mirror::Reflect>() {}
+/// mirrored::<mirror::Array<u8, 33>>();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Array<T, const N: usize>(pub [T; N]);
+
+impl<T, const N: usize> Deref for Array<T, N> {
+    type Target = [T; N];
+
+    fn deref(&self) -> &[T; N] {
+        &self.0
+    }
+}
+
+impl<T, const N: usize> DerefMut for Array<T, N> {
+    fn deref_mut(&mut self) -> &mut [T; N] {
+        &mut self.0
+    }
+}
+
+impl<T, const N: usize> AsRef<[T]> for Array<T, N> {
+    fn as_ref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T, const N: usize> AsMut<[T]> for Array<T, N> {
+    fn as_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+}
+
+impl<T: Serialize, const N: usize> Serialize for Array<T, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for element in self.0.iter() {
+            tuple.serialize_element(element)?;
         }
-    };
+        tuple.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de>, const N: usize> Deserialize<'de> for Array<T, N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let elements = Vec::<T>::deserialize(deserializer)?;
+        let len = elements.len();
+        elements.try_into()
+            .map(Array)
+            .map_err(|_| D::Error::invalid_length(len, &format!("an array of length {}", N).as_str()))
+    }
+}
+
+impl<T: Reflect + Serialize, const N: usize> Reflect for Array<T, N> {
+    fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+        command_fixed(self, context, command)
+    }
+
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+        diff_fixed(&self.0, &new.0)
+    }
 }
 
-array!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
-       26, 27, 28, 29, 30, 31, 32, );
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Inventory {
+        slots: Array<Option<String>, 64>,
+        hotbar: [u8; 4],
+    }
+
+    #[test]
+    fn arrays() {
+        let mut inventory = Inventory { slots: Array(std::array::from_fn(|_| None)), hotbar: [0, 1, 2, 3] };
+        inventory.command_str((), "slots/63/set:\"sword\"").unwrap();
+        inventory.command_str((), "slots/move:63 0").unwrap();
+        inventory.command_str((), "hotbar/swap:0 3").unwrap();
+        inventory.command_str((), "hotbar/*/add:1").unwrap();
+        inventory.command((), &Inventory::cmd().slots().at(1).set(&Some("shield".to_string())).unwrap()).unwrap();
+        assert_eq!(inventory.slots[0], Some("sword".to_string()));
+        assert_eq!(inventory.slots[1], Some("shield".to_string()));
+        assert!(inventory.slots[2..].iter().all(Option::is_none));
+        assert_eq!(inventory.hotbar, [4, 2, 3, 1]);
+
+        assert!(matches!(inventory.command_str((), "slots/64/set:null"), Err(Error::PathError)));
+        assert!(inventory.command_str((), "slots/push:null").is_err());
+        assert!(inventory.command_str((), "hotbar/set:[1, 2]").is_err());
+        assert_eq!(inventory.hotbar, [4, 2, 3, 1]);
+
+        let serialized = serde_json::to_value(&inventory.slots).unwrap();
+        assert_eq!(serialized.as_array().unwrap().len(), 64);
+        let mut slots: Array<Option<String>, 64> = serde_json::from_value(serialized).unwrap();
+        assert_eq!(slots, inventory.slots);
+        slots[5] = Some("bow".to_string());
+        assert_eq!(diff(&inventory.slots, &slots).unwrap()[0].to_string(), "5/set:\"bow\"");
+
+        let mut empty: [u8; 0] = [];
+        empty.command_str((), "set:[]").unwrap();
+    }
+}
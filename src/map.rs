@@ -1,38 +1,81 @@
 use super::*;
-use serde_json::from_value;
+use serde_json::{from_value, to_value};
 use serde_json::from_str;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::Hash;
 
-impl<K: Primitive + Eq + Hash, V: Reflect> Reflect for HashMap<K, V> {
-    fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
-        match command {
-            &Command::Path { ref element, ref command } => {
+/// Returns the map key in `key`. Keys that are not strings are also tried as strings, since json
+///  pointers and json patches write every key as a string.
+fn key<K: Primitive>(key: &Value) -> Result<K, Error> {
+    from_value(key.clone()).or_else(|err| match key {
+        &Value::String(_) => Err(err.into()),
+        _ => Ok(from_value(Value::String(key.to_string()))?),
+    })
+}
 
-                let index: K = from_value(from_str(element.as_str())?)?;
-                let elem: &mut V = self.get_mut(&index).ok_or(Error::PathError)?;
+/// Returns the map key in a path element, which is either json or the key as a plain string.
+fn element_key<K: Primitive>(element: &str) -> Result<K, Error> {
+    match from_str::<Value>(element) {
+        Ok(value) => key(&value).or_else(|_| key(&Value::String(element.to_string()))),
+        Err(_) => key(&Value::String(element.to_string())),
+    }
+}
+
+macro_rules! map {
+    ($map:ident, $($bound:tt)+) => {
+        impl<K: Primitive + $($bound)+, V: Reflect + Serialize> Reflect for $map<K, V> {
+            fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
+                match command {
+                    &Command::Path { ref element, ref command } => {
+                        let index: K = element_key(element)?;
+                        let elem: &mut V = self.get_mut(&index).ok_or(Error::PathError)?;
+
+                        let mut result = Ok(());
+                        context.with_inner(element.as_str(), |c| result=elem.command(c, command));
+                        result
+                    },
+                    &Command::Each { ref elements, ref command } => {
+                        let keys = |map: &Self| map.keys().map(|key| Ok(to_value(key)?.to_string())).collect();
+                        apply_each(self, context, elements, keys, command)
+                    },
+                    &Command::Set { ref value } => {
+                        *self = from_value(value.clone())?;
+                        Ok(())
+                    },
+                    &Command::Insert { ref key, ref value } => {
+                        let key: K = self::key(key)?;
+                        let value: V = from_value(value.clone())?;
+                        self.insert(key, value);
+                        Ok(())
+                    },
+                    &Command::Remove { ref key } => {
+                        let key: K = self::key(key)?;
+                        self.remove(&key);
+                        Ok(())
+                    }
+                    &Command::Get => apply_get(self, context),
+                    &Command::Cas { ref expected, ref value } => apply_cas(self, &context, expected, value),
+                    &Command::Batch { ref commands } => apply_batch(self, context, commands),
+                    &_ => Err(Error::IncompatibleCommand),
+                }
+            }
 
-                let mut result = Ok(());
-                context.with_inner(element.as_str(), |c| result=elem.command(c, command));
-                result
-            },
-            &Command::Set { ref value } => {
-                *self = from_value(value.clone())?;
-                Ok(())
-            },
-            &Command::Insert { ref key, ref value } => {
-                //self.push(from_value(value.clone())?);
-                let key: K = from_value(key.clone())?;
-                let value: V = from_value(value.clone())?;
-                self.insert(key, value);
-                Ok(())
-            },
-            &Command::Remove { ref key } => {
-            	let key: K = from_value(key.clone())?;
-                self.remove(&key);
-                Ok(())
+            fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+                let mut commands = Vec::new();
+                for (key, old) in self.iter() {
+                    match new.get(key) {
+                        Some(new) => diff_child(&to_value(key)?.to_string(), old, new, &mut commands)?,
+                        None => commands.push(Command::Remove { key: to_value(key)? }),
+                    }
+                }
+                for (key, value) in new.iter().filter(|&(key, _)| !self.contains_key(key)) {
+                    commands.push(Command::Insert { key: to_value(key)?, value: to_value(value)? });
+                }
+                Ok(commands)
             }
-            &_ => Err(Error::IncompatibleCommand),
         }
-    }
-}
\ No newline at end of file
+    };
+}
+
+map!(HashMap, Eq + Hash);
+map!(BTreeMap, Ord);
@@ -1,10 +1,10 @@
 use super::*;
 use serde_json::from_value;
 
-impl<T: Reflect> Reflect for Option<T> {
+impl<T: Reflect + Serialize> Reflect for Option<T> {
     fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
         match command {
-            &Command::Path { ref element, ref command } => {
+            Command::Path { element, command } => {
                 if element == "val" {
                     let elem: &mut T = self.as_mut().ok_or(Error::PathError)?;
                     let mut result = Ok(());
@@ -14,15 +14,30 @@ impl<T: Reflect> Reflect for Option<T> {
                     Err(Error::PathError)
                 }
             },
-            &Command::Set { ref value } => {
+            Command::Set { value } => {
                 *self = Some(from_value(value.clone())?);
                 Ok(())
             },
-            &Command::Remove { .. } => {
+            Command::Remove { .. } => {
             	*self = None;
                 Ok(())
             }
-            &_ => Err(Error::IncompatibleCommand),
+            Command::Get => apply_get(self, context),
+            Command::Cas { expected, value } => apply_cas(self, &context, expected, value),
+            Command::Batch { commands } => apply_batch(self, context, commands),
+            _ => Err(Error::IncompatibleCommand),
+        }
+    }
+
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+        match (self, new) {
+            (Some(old), Some(new)) => {
+                let mut commands = Vec::new();
+                diff_child("val", old, new, &mut commands)?;
+                Ok(commands)
+            },
+            (Some(_), None) => Ok(vec![Command::Remove { key: Value::Null }]),
+            (None, _) => diff_set(self, new),
         }
     }
 }
\ No newline at end of file
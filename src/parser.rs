@@ -0,0 +1,344 @@
+use super::*;
+use serde_json::StreamDeserializer;
+use serde_json::de::StrRead;
+use std::fmt::Write;
+
+/// Maximum number of path elements in a command. Deeper paths are rejected, so untrusted input
+///  can't build arbitrarily deep commands.
+pub const MAX_PATH_DEPTH: usize = 256;
+
+/// A parsed path element, before it is turned into a `Command`.
+enum Segment {
+    Element(String),
+    Each(Option<Vec<String>>),
+}
+
+/// Parser for the textual command syntax.
+///
+/// A command is zero or more path elements, each followed by `/`, and an operation:
+///  `{element}/{element}/{name}:{arguments}`.
+/// Path elements come in two forms:
+///  - quoted: a json string such as `"a/b"`, which is kept as is, including the quotes. This is
+///    the form that maps use for their keys.
+///  - bare: any text, where `/`, `:`, `\`, `"`, `*`, `{`, `}` and `,` are escaped with a `\`.
+///
+/// Instead of an element, a path can select several elements at once with `*` or `{a,b}`.
+///
+/// A command that starts with `/` has a json pointer as its path, like `/a/b/set:1`. Bare
+///  elements then also unescape `~1` to `/` and `~0` to `~`, so json pointers can be used as is.
+/// Their tokens may contain `:`, so a `:` only starts the operation if the rest parses as one.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    pointer: bool,
+}
+
+/// Returns the length of the json string at the start of `text`, without validating escapes.
+fn quoted_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    match chars.next() {
+        Some((_, '"')) => (),
+        _ => return None,
+    }
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            },
+            '"' => return Some(i + 1),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Returns whether `element` is written in the quoted form.
+fn is_quoted(element: &str) -> bool {
+    quoted_len(element) == Some(element.len()) && serde_json::from_str::<String>(element).is_ok()
+}
+
+/// Write a path element or function name in a form that `Command::parse` reads back unchanged.
+/// Elements of a json pointer path escape `~` as well.
+pub(crate) fn write_element<W: Write>(w: &mut W, element: &str, pointer: bool) -> fmt::Result {
+    if is_quoted(element) {
+        return w.write_str(element);
+    }
+    for ch in element.chars() {
+        if pointer && ch == '~' {
+            w.write_str("~0")?;
+            continue;
+        }
+        if let '/' | ':' | '\\' | '"' | '*' | '{' | '}' | ',' = ch {
+            w.write_char('\\')?;
+        }
+        w.write_char(ch)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn parse(text: &str) -> Result<Command, Error> {
+    let pointer = text.starts_with('/');
+    Parser { text, pos: if pointer { 1 } else { 0 }, pointer }.command()
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, offset: usize, expected: &str) -> Error {
+        Error::SyntaxError { offset, expected: expected.to_string() }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn command(&mut self) -> Result<Command, Error> {
+        let mut path = Vec::new();
+
+        let operation = loop {
+            if path.len() > MAX_PATH_DEPTH {
+                return Err(self.error(self.pos, "a shorter path"));
+            }
+
+            let start = self.pos;
+            if self.rest().starts_with("*/") {
+                self.pos += 2;
+                path.push(Segment::Each(None));
+                continue;
+            }
+            if self.rest().starts_with('{') {
+                self.pos += 1;
+                path.push(Segment::Each(Some(self.selection()?)));
+                continue;
+            }
+            if let Some(element) = self.quoted()? {
+                self.expect('/', "'/' after a quoted path element")?;
+                path.push(Segment::Element(element));
+                continue;
+            }
+
+            match self.bare(&['/', ':'], "'/' or ':'")? {
+                (element, '/') => path.push(Segment::Element(element)),
+                (name, _) => {
+                    let pointer = self.pointer;
+                    self.pointer = false;
+                    match self.operation(start, &name) {
+                        Ok(operation) => break operation,
+                        // json pointer tokens can contain ':', so it may belong to an element
+                        Err(error) if pointer => {
+                            self.pos = start;
+                            self.pointer = true;
+                            match self.bare(&['/'], "'/'") {
+                                Ok((element, _)) => path.push(Segment::Element(element)),
+                                Err(_) => return Err(error),
+                            }
+                        },
+                        Err(error) => return Err(error),
+                    }
+                },
+            }
+        };
+
+        Ok(path.into_iter().rev().fold(operation, |command, segment| match segment {
+            Segment::Element(element) => Command::Path { element, command: Box::new(command) },
+            Segment::Each(elements) => Command::Each { elements, command: Box::new(command) },
+        }))
+    }
+
+    fn expect(&mut self, ch: char, expected: &str) -> Result<(), Error> {
+        if !self.rest().starts_with(ch) {
+            return Err(self.error(self.pos, expected));
+        }
+        self.pos += ch.len_utf8();
+        Ok(())
+    }
+
+    /// Read a quoted element, if the text continues with one.
+    fn quoted(&mut self) -> Result<Option<String>, Error> {
+        let len = match quoted_len(self.rest()) {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let element = &self.rest()[..len];
+        if serde_json::from_str::<String>(element).is_err() {
+            return Err(self.error(self.pos, "a valid json string"));
+        }
+        self.pos += len;
+        Ok(Some(element.to_string()))
+    }
+
+    /// Read the elements of `{a,b}/`, after the opening brace.
+    fn selection(&mut self) -> Result<Vec<String>, Error> {
+        let mut elements = Vec::new();
+
+        loop {
+            let element = match self.quoted()? {
+                Some(element) => match self.rest().chars().next() {
+                    Some(terminator @ ',') | Some(terminator @ '}') => {
+                        self.pos += 1;
+                        (element, terminator)
+                    },
+                    _ => return Err(self.error(self.pos, "',' or '}' after a quoted path element")),
+                },
+                None => self.bare(&[',', '}'], "',' or '}'")?,
+            };
+
+            elements.push(element.0);
+            if element.1 == '}' {
+                break;
+            }
+        }
+
+        self.expect('/', "'/' after '}'")?;
+        Ok(elements)
+    }
+
+    /// Read a bare element up to and including the next unescaped char in `terminators`.
+    fn bare(&mut self, terminators: &[char], expected: &str) -> Result<(String, char), Error> {
+        let mut element = String::new();
+        let mut chars = self.rest().char_indices();
+        let base = self.pos;
+
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => element.push(escaped),
+                    None => return Err(self.error(base + i + 1, "an escaped character")),
+                },
+                '~' if self.pointer => match chars.next() {
+                    Some((_, '0')) => element.push('~'),
+                    Some((_, '1')) => element.push('/'),
+                    _ => return Err(self.error(base + i + 1, "'0' or '1' after '~'")),
+                },
+                _ if terminators.contains(&ch) => {
+                    self.pos = base + i + 1;
+                    return Ok((element, ch));
+                },
+                _ => element.push(ch),
+            }
+        }
+
+        Err(self.error(self.text.len(), expected))
+    }
+
+    /// Read json values up to the end of the command, requiring exactly `count` if it is `Some`.
+    fn values(&mut self, count: Option<usize>) -> Result<Vec<Value>, Error> {
+        let base = self.pos;
+        let mut stream = StreamDeserializer::<_, Value>::new(StrRead::new(self.rest()));
+        let mut values = Vec::new();
+
+        while count.map(|count| values.len() < count).unwrap_or(true) {
+            match stream.next() {
+                Some(Ok(value)) => values.push(value),
+                Some(Err(_)) => return Err(self.error(base + stream.byte_offset(), "a json value")),
+                None if count.is_none() => break,
+                None => return Err(self.error(self.text.len(), "a json value")),
+            }
+        }
+
+        let end = base + stream.byte_offset();
+        let trailing = &self.text[end..];
+        if !trailing.trim_start().is_empty() {
+            let offset = self.text.len() - trailing.trim_start().len();
+            return Err(self.error(offset, "the end of the command"));
+        }
+        self.pos = self.text.len();
+
+        Ok(values)
+    }
+
+    /// Read exactly `count` indices, followed by the end of the command.
+    fn indices(&mut self, count: usize) -> Result<Vec<usize>, Error> {
+        let offset = self.pos;
+        self.values(Some(count))?.iter()
+            .map(|value| value.as_u64().map(|index| index as usize))
+            .collect::<Option<_>>()
+            .ok_or_else(|| self.error(offset, "indices"))
+    }
+
+    fn operation(&mut self, start: usize, name: &str) -> Result<Command, Error> {
+        match name {
+            "set" => {
+                let mut values = self.values(Some(1))?;
+                Ok(Command::Set { value: values.remove(0) })
+            },
+            "push" => {
+                let mut values = self.values(Some(1))?;
+                Ok(Command::Push { value: values.remove(0) })
+            },
+            "pop" => {
+                self.values(Some(0))?;
+                Ok(Command::Pop)
+            },
+            // sets only take the key, which is the element to insert
+            "insert" => {
+                let offset = self.pos;
+                let mut values = self.values(None)?;
+                if values.is_empty() || values.len() > 2 {
+                    return Err(self.error(offset, "a key and an optional value"));
+                }
+                let key = values.remove(0);
+                Ok(Command::Insert { key, value: values.pop().unwrap_or(Value::Null) })
+            },
+            "remove" => {
+                let mut values = self.values(Some(1))?;
+                Ok(Command::Remove { key: values.remove(0) })
+            },
+            "swap" => {
+                let indices = self.indices(2)?;
+                Ok(Command::Swap { a: indices[0], b: indices[1] })
+            },
+            "move" => {
+                let indices = self.indices(2)?;
+                Ok(Command::Move { from: indices[0], to: indices[1] })
+            },
+            "truncate" => Ok(Command::Truncate { len: self.indices(1)?[0] }),
+            "get" => {
+                self.values(Some(0))?;
+                Ok(Command::Get)
+            },
+            "clear" => {
+                self.values(Some(0))?;
+                Ok(Command::Clear)
+            },
+            "extend" => Ok(Command::Extend { value: self.values(Some(1))?.remove(0) }),
+            "call" => {
+                let (key, _) = self.bare(&[':'], "':' after the function name")?;
+                Ok(Command::Call { key, arguments: self.values(None)? })
+            },
+            "add" => Ok(Command::Add { value: self.values(Some(1))?.remove(0) }),
+            "mul" => Ok(Command::Mul { value: self.values(Some(1))?.remove(0) }),
+            "min" => Ok(Command::Min { value: self.values(Some(1))?.remove(0) }),
+            "max" => Ok(Command::Max { value: self.values(Some(1))?.remove(0) }),
+            "splice" => {
+                let offset = self.pos;
+                let values = self.values(Some(3))?;
+                match (values[0].as_u64(), values[1].as_u64(), values[2].as_str()) {
+                    (Some(start), Some(delete), Some(insert)) => Ok(Command::Splice {
+                        offset: start as usize,
+                        delete: delete as usize,
+                        insert: insert.to_string(),
+                    }),
+                    _ => Err(self.error(offset, "a char offset, a char count and a json string")),
+                }
+            },
+            "cas" => {
+                let mut values = self.values(Some(2))?;
+                let expected = values.remove(0);
+                Ok(Command::Cas { expected, value: values.remove(0) })
+            },
+            "batch" => {
+                let offset = self.pos;
+                let commands = match self.values(Some(1))?.remove(0) {
+                    Value::Array(commands) => commands,
+                    _ => return Err(self.error(offset, "a json array of commands")),
+                };
+                let commands = commands.iter()
+                    .map(|command| command.as_str().and_then(|command| parse(command).ok()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| self.error(offset, "a json array of valid commands"))?;
+                Ok(Command::Batch { commands })
+            },
+            _ => Err(self.error(start, "a path element or a command name")),
+        }
+    }
+}
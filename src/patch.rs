@@ -0,0 +1,243 @@
+use super::*;
+use serde_json::{from_str, to_value, Map};
+
+/// Returns the path elements of a json pointer (RFC 6901), such as `/a/b~1c` for `a` and `b/c`.
+fn parse_pointer(pointer: &str) -> Result<Vec<String>, Error> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !pointer.starts_with('/') {
+        return Err(Error::Command(format!("invalid json pointer {}", pointer)));
+    }
+    Ok(pointer[1..].split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+/// Returns `token` as a json pointer token, including the `/` before it.
+fn escape(token: &str) -> String {
+    format!("/{}", token.replace('~', "~0").replace('/', "~1"))
+}
+
+/// Returns the json pointer token of a path element. Quoted elements, as used for map keys, are
+///  written as the string they contain.
+fn pointer_token(element: &str) -> String {
+    match from_str::<String>(element) {
+        Ok(key) => escape(&key),
+        Err(_) => escape(element),
+    }
+}
+
+/// Returns the key that a json pointer token names in a list or map.
+fn token_key(token: &str) -> Value {
+    match token.parse::<u64>() {
+        Ok(index) => index.into(),
+        Err(_) => Value::String(token.to_string()),
+    }
+}
+
+/// Returns the json pointer token of a key in a list or map.
+fn key_token(key: &Value) -> String {
+    match key {
+        Value::String(key) => escape(key),
+        key => escape(&key.to_string()),
+    }
+}
+
+fn operation(op: &str, path: String, value: Option<&Value>) -> Value {
+    let mut operation = Map::new();
+    operation.insert("op".to_string(), op.into());
+    operation.insert("path".to_string(), path.into());
+    if let Some(value) = value {
+        operation.insert("value".to_string(), value.clone());
+    }
+    Value::Object(operation)
+}
+
+fn to_operations(command: &Command, path: &str, patch: &mut Vec<Value>) -> Result<(), Error> {
+    match command {
+        Command::Path { element, command } => to_operations(command, &(path.to_string() + &pointer_token(element)), patch)?,
+        Command::Set { value } => patch.push(operation("replace", path.to_string(), Some(value))),
+        Command::Push { value } => patch.push(operation("add", format!("{}/-", path), Some(value))),
+        Command::Insert { key, value } => patch.push(operation("add", path.to_string() + &key_token(key), Some(value))),
+        Command::Remove { key } => patch.push(operation("remove", path.to_string() + &key_token(key), None)),
+        Command::Move { from, to } => {
+            let mut operation = operation("move", format!("{}/{}", path, to), None);
+            operation["from"] = Value::String(format!("{}/{}", path, from));
+            patch.push(operation);
+        },
+        Command::Cas { expected, value } => {
+            patch.push(operation("test", path.to_string(), Some(expected)));
+            if value != expected {
+                patch.push(operation("replace", path.to_string(), Some(value)));
+            }
+        },
+        Command::Batch { commands } => {
+            for command in commands {
+                to_operations(command, path, patch)?;
+            }
+        },
+        &_ => return Err(Error::IncompatibleCommand),
+    }
+    Ok(())
+}
+
+/// Convert a command into a json patch (RFC 6902), an array of operations with json pointer paths.
+/// Only commands that json patch can express are converted: `set:`, `push:`, `insert:`,
+///  `remove:`, `move:`, `cas:` and batches of these. Others fail with `Error::IncompatibleCommand`.
+pub fn to_patch(command: &Command) -> Result<Value, Error> {
+    let mut patch = Vec::new();
+    to_operations(command, "", &mut patch)?;
+    Ok(Value::Array(patch))
+}
+
+/// Convert one operation, given the serialized `shape` of the value it is applied to.
+fn from_operation(shape: &Value, operation: &Value) -> Result<Command, Error> {
+    let field = |name: &str| operation.get(name).ok_or_else(|| Error::Command(format!("json patch operation without {}", name)));
+    let pointer = |name: &str| field(name)?.as_str()
+        .ok_or_else(|| Error::Command(format!("json patch operation with an invalid {}", name)))
+        .and_then(parse_pointer);
+
+    let mut path = pointer("path")?;
+    let last = path.last().cloned();
+    let command = match (field("op")?.as_str().unwrap_or(""), last.as_deref()) {
+        ("replace", _) | ("add", None) => Command::Set { value: field("value")?.clone() },
+        // a cas that sets the value it expects only compares
+        ("test", _) => Command::Cas { expected: field("value")?.clone(), value: field("value")?.clone() },
+        ("add", Some("-")) => {
+            path.pop();
+            Command::Push { value: field("value")?.clone() }
+        },
+        ("add", Some(last)) => {
+            // adding a member that an object already has replaces it, which is the only way to
+            //  add to the fields of a struct
+            let pointer = field("path")?.as_str().unwrap_or("");
+            match shape.pointer(&pointer[..pointer.rfind('/').unwrap_or(0)]) {
+                Some(Value::Object(members)) if members.contains_key(last) => {
+                    Command::Set { value: field("value")?.clone() }
+                },
+                _ => {
+                    path.pop();
+                    Command::Insert { key: token_key(last), value: field("value")?.clone() }
+                },
+            }
+        },
+        ("remove", Some(last)) => {
+            path.pop();
+            Command::Remove { key: token_key(last) }
+        },
+        // mirror only moves elements within a list
+        ("move", Some(last)) => {
+            let mut from = pointer("from")?;
+            path.pop();
+            match (from.pop().and_then(|from| from.parse().ok()), last.parse()) {
+                (Some(from_index), Ok(to)) if from == path => Command::Move { from: from_index, to },
+                _ => return Err(Error::IncompatibleCommand),
+            }
+        },
+        _ => return Err(Error::IncompatibleCommand),
+    };
+
+    Ok(path.into_iter().rev().fold(command, |command, element| Command::Path { element, command: Box::new(command) }))
+}
+
+/// Convert a json patch (RFC 6902) for `value` into a command. A patch with several operations
+///  becomes a batch, so it is applied atomically like json patch requires.
+/// `value` tells apart adding a member to an object, which becomes `insert:`, from replacing one
+///  it already has, like a field of a struct, which becomes `set:`.
+/// The `add`, `remove`, `replace`, `move` and `test` operations are supported, where `move` can
+///  only move an element within a list. Others fail with `Error::IncompatibleCommand`.
+pub fn from_patch<R: Serialize>(value: &R, patch: &Value) -> Result<Command, Error> {
+    let operations = patch.as_array().ok_or_else(|| Error::Command("json patch is not an array".into()))?;
+    let mut shape = to_value(value)?;
+    let mut commands = Vec::new();
+    for operation in operations {
+        let command = from_operation(&shape, operation)?;
+        // later operations see the changes of earlier ones; failures show when the patch is applied
+        let _ = shape.command((), &command);
+        commands.push(command);
+    }
+
+    if commands.len() == 1 {
+        return Ok(commands.remove(0));
+    }
+    Ok(Command::Batch { commands })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{Attributes, Lobby, Position, Seat};
+
+    #[test]
+    fn json_patch() {
+        let seat = || Seat { ready: true, position: Position { x: 1, y: 2 } };
+        let mut lobby = Lobby {
+            seats: vec![seat(), seat()],
+            scores: vec![("a/b".to_string(), 1), ("c".to_string(), 2)].into_iter().collect(),
+        };
+
+        // json pointers work as paths, including for map keys
+        lobby.command_str((), "/seats/1/ready/set:false").unwrap();
+        lobby.command_str((), "/scores/a~1b/add:10").unwrap();
+        lobby.command_str((), "/scores/\"c\"/add:10").unwrap();
+        assert!(!lobby.seats[1].ready);
+        assert_eq!((lobby.scores["a/b"], lobby.scores["c"]), (11, 12));
+        assert_eq!(Command::parse("/a~0~1b/set:1").unwrap(), Command::parse("a~\\/b/set:1").unwrap());
+        assert!(Command::parse("/a~2/set:1").is_err());
+        // json pointer tokens can contain ':'
+        assert_eq!(Command::parse("/a:b/set:1").unwrap(), Command::parse("a\\:b/set:1").unwrap());
+        assert_eq!(Command::parse("/set:1/c:d/pop:").unwrap().to_string(), "set\\:1/c\\:d/pop:");
+        assert!(Command::parse("/a/set:1 2").is_err());
+
+        let command = Command::Path { element: "".into(), command: Box::new(Command::parse("a~b/pop:").unwrap()) };
+        assert_eq!(command.to_string(), "//a~0b/pop:");
+        assert_eq!(Command::parse(&command.to_string()).unwrap(), command);
+
+        let patch = serde_json::json!([
+            { "op": "test", "path": "/seats/0/ready", "value": true },
+            { "op": "replace", "path": "/seats/0/position/x", "value": 5 },
+            { "op": "add", "path": "/seats/-", "value": { "ready": false, "position": { "x": 0, "y": 0 } } },
+            { "op": "move", "from": "/seats/2", "path": "/seats/0" },
+            { "op": "add", "path": "/scores/d", "value": 4 },
+            { "op": "remove", "path": "/scores/a~1b" },
+        ]);
+        let command = from_patch(&lobby, &patch).unwrap();
+        lobby.command((), &command).unwrap();
+        assert_eq!(lobby.seats.len(), 3);
+        assert_eq!(lobby.seats[1].position.x, 5);
+        assert!(!lobby.seats[0].ready);
+        assert_eq!(lobby.scores.len(), 2);
+        assert_eq!(lobby.scores["d"], 4);
+        assert_eq!(to_patch(&command).unwrap(), patch);
+
+        // a failing test rolls back the whole patch
+        let patch = serde_json::json!([
+            { "op": "remove", "path": "/scores/d" },
+            { "op": "test", "path": "/scores/c", "value": 0 },
+        ]);
+        assert!(matches!(lobby.command((), &from_patch(&lobby, &patch).unwrap()), Err(Error::Conflict(_))));
+        assert_eq!(lobby.scores["d"], 4);
+
+        // tests only compare, so they work on read-only fields
+        let mut attributes = Attributes { health: 10, level: 1, cache: Default::default() };
+        let test = from_patch(&attributes, &serde_json::json!([{ "op": "test", "path": "/level", "value": 1 }])).unwrap();
+        assert!(!test.is_mutation());
+        attributes.command((), &test).unwrap();
+        assert!(matches!(attributes.command_str((), "level/cas:1 2"), Err(Error::ReadOnly)));
+
+        // adding a member that exists replaces it, so it works on the fields of a struct
+        let patch = serde_json::json!([
+            { "op": "add", "path": "/seats/0/ready", "value": true },
+            { "op": "remove", "path": "/scores/d" },
+            { "op": "add", "path": "/scores/d", "value": 5 },
+            { "op": "add", "path": "/scores/d", "value": 6 },
+        ]);
+        let command = from_patch(&lobby, &patch).unwrap();
+        assert_eq!(command.to_string(), "batch:[\"seats/0/ready/set:true\",\"scores/remove:\\\"d\\\"\",\"scores/insert:\\\"d\\\" 5\",\"scores/d/set:6\"]");
+        lobby.command((), &command).unwrap();
+        assert!(lobby.seats[0].ready);
+        assert_eq!(lobby.scores["d"], 6);
+
+        assert!(from_patch(&lobby, &serde_json::json!([{ "op": "copy", "from": "/a", "path": "/b" }])).is_err());
+        assert!(to_patch(&Command::parse("seats/pop:").unwrap()).is_err());
+    }
+}
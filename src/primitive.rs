@@ -1,32 +1,172 @@
 use super::*;
 use serde_json::from_value;
+use std::convert::TryFrom;
 
-pub trait Primitive: for<'de> Deserialize<'de> { }
+/// Values without parts, like numbers and strings, which can only be replaced as a whole or
+///  changed by the commands of the hooks below. They implement `Reflect` with `apply_primitive`.
+/// Since 0.2 implementing `Primitive` no longer implements `Reflect`, which is what lets `Box<T>`
+///  and tuples have their own implementations. Other primitives implement `Reflect::command` by
+///  calling `apply_primitive`.
+pub trait Primitive: Serialize + for<'de> Deserialize<'de> {
+    /// Apply `Command::Add`, `Command::Mul`, `Command::Min` or `Command::Max`.
+    /// Only numeric primitives support these commands.
+    fn arithmetic(&mut self, _command: &Command) -> Result<(), Error> {
+        Err(Error::IncompatibleCommand)
+    }
 
-impl<T: Primitive> Reflect for T {
-    fn command<C: Context>(&mut self, _: C, command: &Command) -> Result<(), Error> {
-        match command {
-            &Command::Set{ ref value } => {
-                *self = from_value(value.clone())?;
-                Ok(())
-            },
-            &_ => Err(Error::IncompatibleCommand),
-        }
+    /// Apply `Command::Splice`. Only `String` supports this command.
+    fn splice(&mut self, _offset: usize, _delete: usize, _insert: &str) -> Result<(), Error> {
+        Err(Error::IncompatibleCommand)
+    }
+}
+
+/// Primitives that support `Command::Add`, `Command::Mul`, `Command::Min` and `Command::Max`.
+pub trait Numeric: Primitive {
+    /// The type of the deltas and factors of `add:` and `mul:`. Integers take an `i128`, so
+    ///  unsigned integers can be decremented.
+    type Delta: Serialize;
+}
+
+/// Execute `command` on a primitive, used by the `Reflect` implementations of primitives.
+pub fn apply_primitive<T: Primitive + Reflect, C: Context>(value: &mut T, context: C, command: &Command) -> Result<(), Error> {
+    match command {
+        Command::Set { value: new } => {
+            *value = from_value(new.clone())?;
+            Ok(())
+        },
+        Command::Add { .. } | Command::Mul { .. } | Command::Min { .. } | Command::Max { .. } => {
+            value.arithmetic(command)
+        },
+        Command::Splice { offset, delete, insert } => value.splice(*offset, *delete, insert),
+        Command::Get => apply_get(value, context),
+        Command::Cas { expected, value: new } => apply_cas(value, &context, expected, new),
+        Command::Batch { commands } => apply_batch(value, context, commands),
+        &_ => Err(Error::IncompatibleCommand),
     }
 }
 
+/// A single implementation for every primitive would keep `Box<T>` from having one, so every
+///  primitive type is listed here.
+macro_rules! reflect {
+    ($($p:ty),*) => { $(
+        impl Reflect for $p {
+            fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+                apply_primitive(self, context, command)
+            }
+        }
+    )* };
+}
+
 macro_rules! primitive {
     ($($pp:ty,)*) => { $(primitive!($pp);)* };
     ($p:ty) => { impl Primitive for $p { } };
 }
 
+/// Tuples of primitives are still primitives, but they implement `Reflect` with paths to their
+///  positions, see `tuple.rs`.
 macro_rules! tuple {
     ($($p:ident),*) => {
         impl<$( $p : Primitive ),* > Primitive for ($($p),*) { }
     };
 }
 
-primitive!(bool, i8, u8, i16, u16, i32, u32, f32, i64, u64, f64, isize, usize, String, );
+/// Integers compute in `i128`, so unsigned integers can be decremented with a negative delta.
+/// Results that don't fit in the integer type fail with `Error::Overflow`.
+macro_rules! integer {
+    ($($int:ty),*) => { $(
+        impl Numeric for $int {
+            type Delta = i128;
+        }
+
+        impl Primitive for $int {
+            fn arithmetic(&mut self, command: &Command) -> Result<(), Error> {
+                let result = match command {
+                    Command::Add { value } => (*self as i128).checked_add(from_value(value.clone())?),
+                    Command::Mul { value } => (*self as i128).checked_mul(from_value(value.clone())?),
+                    Command::Min { value } => Some((*self).min(from_value(value.clone())?) as i128),
+                    Command::Max { value } => Some((*self).max(from_value(value.clone())?) as i128),
+                    &_ => return Err(Error::IncompatibleCommand),
+                };
+
+                *self = result.and_then(|result| <$int>::try_from(result).ok()).ok_or(Error::Overflow)?;
+                Ok(())
+            }
+        }
+    )* };
+}
+
+/// Results that are not finite fail with `Error::Overflow`.
+macro_rules! float {
+    ($($float:ty),*) => { $(
+        impl Numeric for $float {
+            type Delta = $float;
+        }
+
+        impl Primitive for $float {
+            fn arithmetic(&mut self, command: &Command) -> Result<(), Error> {
+                let result = match command {
+                    Command::Add { value } => *self + from_value::<$float>(value.clone())?,
+                    Command::Mul { value } => *self * from_value::<$float>(value.clone())?,
+                    Command::Min { value } => self.min(from_value(value.clone())?),
+                    Command::Max { value } => self.max(from_value(value.clone())?),
+                    &_ => return Err(Error::IncompatibleCommand),
+                };
+
+                if !result.is_finite() {
+                    return Err(Error::Overflow);
+                }
+                *self = result;
+                Ok(())
+            }
+        }
+    )* };
+}
+
+impl Numeric for u128 {
+    type Delta = i128;
+}
+
+/// `u128` doesn't fit in `i128`, so negative deltas are subtracted instead.
+impl Primitive for u128 {
+    fn arithmetic(&mut self, command: &Command) -> Result<(), Error> {
+        let result = match command {
+            Command::Add { value } => match from_value::<i128>(value.clone())? {
+                delta if delta < 0 => self.checked_sub(delta.unsigned_abs()),
+                delta => self.checked_add(delta as u128),
+            },
+            Command::Mul { value } => match from_value::<i128>(value.clone())? {
+                factor if factor < 0 => Some(0).filter(|_| *self == 0),
+                factor => self.checked_mul(factor as u128),
+            },
+            Command::Min { value } => Some((*self).min(from_value(value.clone())?)),
+            Command::Max { value } => Some((*self).max(from_value(value.clone())?)),
+            &_ => return Err(Error::IncompatibleCommand),
+        };
+
+        *self = result.ok_or(Error::Overflow)?;
+        Ok(())
+    }
+}
+
+/// Offsets count chars, so splices can't split a multi-byte character.
+impl Primitive for String {
+    fn splice(&mut self, offset: usize, delete: usize, insert: &str) -> Result<(), Error> {
+        let mut boundaries = self.char_indices().map(|(i, _)| i).chain(Some(self.len()));
+        let start = boundaries.nth(offset).ok_or(Error::OutOfRange)?;
+        let end = if delete == 0 {
+            start
+        } else {
+            boundaries.nth(delete - 1).ok_or(Error::OutOfRange)?
+        };
+
+        self.replace_range(start..end, insert);
+        Ok(())
+    }
+}
+
+primitive!(bool, char, (), );
+integer!(i8, u8, i16, u16, i32, u32, i64, u64, i128, isize, usize);
+float!(f32, f64);
 tuple!(A, B);
 tuple!(A, B, C);
 tuple!(A, B, C, D);
@@ -41,4 +181,5 @@ tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
 tuple!(A, B, C, D, E, F, G, H, I, J, K, L, M);
 tuple!(A, B, C, D, E, F, G, H, I, J, K, L, M, N);
 tuple!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O);
-tuple!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
\ No newline at end of file
+tuple!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+reflect!(bool, char, (), i8, u8, i16, u16, i32, u32, i64, u64, i128, u128, isize, usize, f32, f64, String);
@@ -1,16 +1,19 @@
 use super::*;
 use crate::reply::Reply;
+use crate::splice::SpliceLog;
 
 use std::ops::Deref;
 use std::sync::mpsc::Receiver;
+use serde_json::Value;
 
 pub struct PrivateClient<T: Reflect + Serialize, R: Remote> {
     value: T,
     remote: R,
+    splices: SpliceLog,
 }
 
 pub struct PrivateServer<T: Reflect + Serialize, R: Remote> {
-    factory: Box<Fn() -> T>,
+    factory: Box<dyn Fn() -> T>,
     listener: Receiver<R>,
     clients: Vec<PrivateClient<T, R>>,
 }
@@ -38,26 +41,37 @@ impl<T: Reflect + Serialize, R: Remote> PrivateServer<T, R> {
 
             // send over the base value to the remote as part of the protocol
             if remote.send(serde_json::to_string(&value).unwrap().as_str()).is_ok() {
-                self.clients.push(PrivateClient { value, remote });
+                self.clients.push(PrivateClient { value, remote, splices: SpliceLog::default() });
             }
         }
 
         for client in self.clients.iter_mut() {
             let mut failed = false;
-            let reply = Reply::new(Vec::new());
+            let reply = Reply::from_remote(Vec::new());
+            let mut responses = Vec::new();
 
             for message in client.remote.iter() {
-                if client.value.command_str(reply.clone(), message.as_str()).is_err() {
+                if let Some((id, cmd)) = request::parse_request(message.as_str()) {
+                    // drop responses left by earlier commands, like calls in a failed batch
+                    reply.take_response();
+                    let result = client.splices.execute(&mut client.value, None, &reply, cmd)
+                        .map(|_| reply.take_response().unwrap_or(Value::Null));
+                    responses.push(request::response(id, &result));
+                } else if client.splices.execute(&mut client.value, None, &reply, message.as_str()).is_err() {
                     failed = true;
                 }
             }
 
-            for (r, send) in reply.into_inner().into_iter() {
-                if send {
-                    failed &= client.remote.send(r.as_str()).is_err();
+            for (r, audience) in reply.into_inner().into_iter() {
+                if audience.includes(true) {
+                    failed |= client.remote.send(r.as_str()).is_err();
                 }
             }
 
+            for r in responses.iter() {
+                failed |= client.remote.send(r.as_str()).is_err();
+            }
+
             if failed {
                 client.remote.close();
             }
@@ -22,7 +22,7 @@ pub trait Remote {
 
     /// Returns an iterator over the available messages. When there are no more messages available
     /// at this time the iterator will yield `None`. The returned iterator will never block.
-    fn iter(&mut self) -> Iter<Self> {
+    fn iter(&mut self) -> Iter<'_, Self> {
         Iter(self)
     }
 }
@@ -2,26 +2,68 @@ use super::*;
 use std::rc::Rc;
 use std::cell::RefCell;
 
+/// The remotes that a reply message is sent to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Audience {
+    /// Every remote, including the origin of the command.
+    All,
+    /// Every remote except the origin.
+    Others,
+    /// Only the origin. Used when a batch has to be split up.
+    Origin,
+}
+
+impl Audience {
+    /// Returns whether a remote receives the message, given whether it is the origin.
+    pub fn includes(self, origin: bool) -> bool {
+        match self {
+            Audience::All => true,
+            Audience::Others => !origin,
+            Audience::Origin => origin,
+        }
+    }
+}
+
 /// Context that will manage a list of reply messages.
 /// Every command executed through the context will be added to the list of reply messages.
 /// After the context is done, the messages will be sent to the remote(s).
+/// The return value of a called function is kept as the response, to be sent only to the origin.
 #[derive(Clone)]
 pub struct Reply {
-    reply: Rc<RefCell<Vec<(String, bool)>>>,
+    reply: Rc<RefCell<Vec<(String, Audience)>>>,
+    response: Rc<RefCell<Option<Value>>>,
     path: String,
+    remote: bool,
 }
 
 impl Reply {
-    /// Create a new reply context. Takes a `Vec` of `(String, bool)` to put the reply messages in.
-    /// The bool in the tuple specifies whether to send the message back to the origin.
-    pub fn new(reply: Vec<(String, bool)>) -> Self {
+    /// Create a new reply context. Takes a `Vec` of `(String, Audience)` to put the reply messages in.
+    pub fn new(reply: Vec<(String, Audience)>) -> Self {
         Self {
             reply: Rc::new(RefCell::new(reply)),
+            response: Rc::new(RefCell::new(None)),
             path: "".to_string(),
+            remote: false,
         }
     }
 
-    pub fn into_inner(self) -> Vec<(String, bool)> {
+    /// Create a new reply context for the commands of a remote, which can't change read-only
+    ///  fields by replacing the values they are in.
+    pub fn from_remote(reply: Vec<(String, Audience)>) -> Self {
+        Self { remote: true, ..Self::new(reply) }
+    }
+
+    /// Take the response of the last called function, if any.
+    pub fn take_response(&self) -> Option<Value> {
+        self.response.borrow_mut().take()
+    }
+
+    /// Schedule a message that was not sent through `Context::command`.
+    pub fn push(&self, message: String, audience: Audience) {
+        self.reply.borrow_mut().push((format!("{}{}", self.path, message), audience));
+    }
+
+    pub fn into_inner(self) -> Vec<(String, Audience)> {
         Rc::try_unwrap(self.reply).unwrap().into_inner()
     }
 }
@@ -34,7 +76,8 @@ impl Context for Reply {
         S: AsRef<str>
     {
         value.command_str((), cmd.as_ref())?;
-        Ok(self.reply.borrow_mut().push((format!("{}{}", self.path, cmd.as_ref()), true)))
+        self.reply.borrow_mut().push((format!("{}{}", self.path, cmd.as_ref()), Audience::All));
+        Ok(())
     }
 
     fn local_command<R, S>(&mut self, value: &mut R, cmd: S) -> Result<(), Error> where
@@ -42,13 +85,62 @@ impl Context for Reply {
         S: AsRef<str>
     {
         value.command_str((), cmd.as_ref())?;
-        Ok(self.reply.borrow_mut().push((format!("{}{}", self.path, cmd.as_ref()), false)))
+        self.reply.borrow_mut().push((format!("{}{}", self.path, cmd.as_ref()), Audience::Others));
+        Ok(())
     }
 
     fn with_inner<F: FnMut(Self::Inner)>(&mut self, path: &str, mut f: F) {
+        let mut inner = self.path.clone();
+        if !path.is_empty() {
+            // elements are unescaped, so they have to be escaped again to be sent
+            parser::write_element(&mut inner, path, false).unwrap();
+            inner.push('/');
+        }
         f(Reply {
             reply: self.reply.clone(),
-            path: format!("{}{}/", self.path, path)
+            response: self.response.clone(),
+            path: inner,
+            remote: self.remote,
         });
     }
+
+    fn respond(&mut self, value: Value) {
+        *self.response.borrow_mut() = Some(value);
+    }
+
+    fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    fn batch<F: FnMut(Self) -> Result<(), Error>>(&mut self, mut f: F) -> Result<(), Error> {
+        let batch = Reply {
+            reply: Rc::new(RefCell::new(Vec::new())),
+            response: self.response.clone(),
+            path: "".to_string(),
+            remote: self.remote,
+        };
+        f(batch.clone())?;
+
+        // the origin may not receive all messages, in which case it gets a batch of its own
+        let messages = batch.reply.take();
+        let commands = |origin: bool| messages.iter()
+            .filter(|&&(_, audience)| audience.includes(origin))
+            .map(|(message, _)| Command::parse(message))
+            .collect::<Result<Vec<_>, _>>();
+        let (others, origin) = (commands(false)?, commands(true)?);
+
+        let batches = if others == origin {
+            vec![(others, Audience::All)]
+        } else {
+            vec![(others, Audience::Others), (origin, Audience::Origin)]
+        };
+
+        for (commands, audience) in batches {
+            if !commands.is_empty() {
+                let message = format!("{}{}", self.path, Command::Batch { commands });
+                self.reply.borrow_mut().push((message, audience));
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,104 @@
+use super::*;
+use futures::*;
+use futures::sync::oneshot;
+
+/// A request wraps a command together with an id, so that the outcome of the command can be
+///  sent back to the remote that made the request.
+/// syntax: `req:{id}:{command}`
+const REQUEST: &str = "req:";
+
+/// Sent back to the origin of a request when the command succeeded.
+/// The value is the serialized return value of a `Command::Call`, or `null` for other commands.
+/// syntax: `res:{id}:{value-json}`
+const RESPONSE: &str = "res:";
+
+/// Sent back to the origin of a request when a `Command::Cas` found a different value, so the
+///  client can tell conflicts apart from other failures.
+/// syntax: `cnf:{id}:{current-json}`
+const CONFLICT: &str = "cnf:";
+
+/// Sent back to the origin of a request when the command failed.
+/// syntax: `err:{id}:{message-json}`
+const FAILURE: &str = "err:";
+
+/// Splits a message in the id and the remainder for messages that start with `prefix`.
+pub(crate) fn split_id<'a>(message: &'a str, prefix: &str) -> Option<(u64, &'a str)> {
+    if !message.starts_with(prefix) {
+        return None;
+    }
+
+    let message = &message[prefix.len()..];
+    let end = message.find(':')?;
+    Some((message[..end].parse().ok()?, &message[end + 1..]))
+}
+
+pub(crate) fn request(id: u64, command: &str) -> String {
+    format!("{}{}:{}", REQUEST, id, command)
+}
+
+/// Parse a request message into its id and command.
+/// Returns `None` if the message is a plain command.
+pub(crate) fn parse_request(message: &str) -> Option<(u64, &str)> {
+    split_id(message, REQUEST)
+}
+
+pub(crate) fn response(id: u64, result: &Result<Value, Error>) -> String {
+    match result {
+        Ok(value) => format!("{}{}:{}", RESPONSE, id, value),
+        Err(Error::Conflict(current)) => format!("{}{}:{}", CONFLICT, id, current),
+        Err(error) => format!("{}{}:{}", FAILURE, id, Value::String(error.to_string())),
+    }
+}
+
+/// Parse a response message into the id of the request and its outcome.
+/// Returns `None` if the message is not a response.
+pub(crate) fn parse_response(message: &str) -> Option<(u64, Result<Value, Error>)> {
+    if let Some((id, value)) = split_id(message, RESPONSE) {
+        Some((id, serde_json::from_str(value).map_err(Error::from)))
+    } else if let Some((id, current)) = split_id(message, CONFLICT) {
+        Some((id, match serde_json::from_str(current) {
+            Ok(current) => Err(Error::Conflict(current)),
+            Err(e) => Err(Error::from(e)),
+        }))
+    } else if let Some((id, error)) = split_id(message, FAILURE) {
+        Some((id, match serde_json::from_str(error) {
+            Ok(message) => Err(Error::Command(message)),
+            Err(e) => Err(Error::from(e)),
+        }))
+    } else {
+        None
+    }
+}
+
+/// Future for the outcome of a request made through `Client::request`.
+/// Resolves once `Client::update` receives the response from the server.
+pub struct Response(oneshot::Receiver<Result<Value, Error>>);
+
+impl Response {
+    pub(crate) fn new() -> (oneshot::Sender<Result<Value, Error>>, Self) {
+        let (sender, receiver) = oneshot::channel();
+        (sender, Response(receiver))
+    }
+
+    /// Returns the outcome if it has been received, without waiting for it.
+    pub(crate) fn try_take(&mut self) -> Option<Result<Value, Error>> {
+        match self.0.try_recv() {
+            Ok(result) => result,
+            Err(_) => Some(Err(Error::ConnectionDropped)),
+        }
+    }
+}
+
+impl Future for Response {
+    type Item = Value;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll() {
+            Ok(Async::Ready(Ok(value))) => Ok(Async::Ready(value)),
+            Ok(Async::Ready(Err(error))) => Err(error),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(Error::ConnectionDropped),
+        }
+    }
+}
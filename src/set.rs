@@ -0,0 +1,55 @@
+use super::*;
+use serde_json::{from_value, to_value};
+use std::collections::{BTreeSet, HashSet};
+use std::hash::Hash;
+
+/// Sets have no paths into their elements. `insert:` and `remove:` take the element itself,
+///  as in `insert:"a"`.
+macro_rules! set {
+    ($set:ident, $($bound:tt)+) => {
+        impl<T: Primitive + $($bound)+> Reflect for $set<T> {
+            fn command<C: Context>(&mut self, context: C, command: &Command) -> Result<(), Error> {
+                match command {
+                    Command::Set { value } => {
+                        *self = from_value(value.clone())?;
+                        Ok(())
+                    },
+                    Command::Insert { key, .. } => {
+                        self.insert(from_value(key.clone())?);
+                        Ok(())
+                    },
+                    Command::Remove { key } => {
+                        self.remove(&from_value(key.clone())?);
+                        Ok(())
+                    },
+                    Command::Clear => {
+                        self.clear();
+                        Ok(())
+                    },
+                    Command::Extend { value } => {
+                        self.extend(from_value::<Vec<T>>(value.clone())?);
+                        Ok(())
+                    },
+                    Command::Get => apply_get(self, context),
+                    Command::Cas { expected, value } => apply_cas(self, &context, expected, value),
+                    Command::Batch { commands } => apply_batch(self, context, commands),
+                    &_ => Err(Error::IncompatibleCommand),
+                }
+            }
+
+            fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+                let mut commands = Vec::new();
+                for element in self.difference(new) {
+                    commands.push(Command::Remove { key: to_value(element)? });
+                }
+                for element in new.difference(self) {
+                    commands.push(Command::Insert { key: to_value(element)?, value: Value::Null });
+                }
+                Ok(commands)
+            }
+        }
+    };
+}
+
+set!(HashSet, Eq + Hash);
+set!(BTreeSet, Ord);
@@ -1,13 +1,18 @@
 use super::*;
 use crate::reply::Reply;
+use crate::splice::SpliceLog;
 use std::ops::Deref;
 use std::sync::mpsc::Receiver;
 use serde::Serialize;
+use serde_json::Value;
 
 pub struct SharedServer<T: Reflect + Serialize, R: Remote> {
     value: T,
     listener: Receiver<R>,
-    clients: Vec<R>,
+    /// Connected clients, with an id that stays the same while they are connected.
+    clients: Vec<(u64, R)>,
+    next_client: u64,
+    splices: SpliceLog,
 }
 
 impl<T: Reflect + Serialize, R: Remote> Deref for SharedServer<T, R> {
@@ -24,22 +29,39 @@ impl<T: Reflect + Serialize, R: Remote> SharedServer<T, R> {
             value,
             listener,
             clients: Vec::new(),
+            next_client: 0,
+            splices: SpliceLog::default(),
         }
     }
 
     pub fn update(&mut self) {
         for mut new_client in self.listener.try_iter() {
-            if new_client.send(serde_json::to_string(&self.value).unwrap().as_str()).is_ok() {
-                self.clients.push(new_client);
+            // clients start at revision 0, so they only need to be told about later revisions
+            let revision = self.splices.revision();
+            if new_client.send(serde_json::to_string(&self.value).unwrap().as_str()).is_ok() &&
+                (revision == 0 || new_client.send(splice::revision(revision, "").as_str()).is_ok()) {
+                self.clients.push((self.next_client, new_client));
+                self.next_client += 1;
             }
         }
 
         for client_id in 0..self.clients.len() {
             let mut failed = false;
-            let reply = Reply::new(Vec::new());
+            let reply = Reply::from_remote(Vec::new());
+            let mut responses = Vec::new();
+            let (origin, ref mut client) = self.clients[client_id];
+
+            for message in client.iter() {
+                if let Some((id, cmd)) = request::parse_request(message.as_str()) {
+                    // drop responses left by earlier commands, like calls in a failed batch
+                    reply.take_response();
+                    let result = self.splices.execute(&mut self.value, Some(origin), &reply, cmd)
+                        .map(|_| reply.take_response().unwrap_or(Value::Null));
+                    responses.push(request::response(id, &result));
+                    continue;
+                }
 
-            for message in self.clients[client_id].iter() {
-                match self.value.command_str(reply.clone(), message.as_str()) {
+                match self.splices.execute(&mut self.value, Some(origin), &reply, message.as_str()) {
                     Ok(_) => (),
                     Err(e) => {
                         failed = true;
@@ -52,43 +74,62 @@ impl<T: Reflect + Serialize, R: Remote> SharedServer<T, R> {
             let reply = reply.into_inner();
 
             for sendto_id in 0..self.clients.len() {
-                for (msg, send) in reply.iter() {
-                    if *send || sendto_id != client_id {
-                        if self.clients[sendto_id].send(msg.as_str()).is_err() {
-                            self.clients[sendto_id].close();
-                        }
+                for (msg, audience) in reply.iter() {
+                    if audience.includes(sendto_id == client_id) &&
+                        self.clients[sendto_id].1.send(msg.as_str()).is_err() {
+                        self.clients[sendto_id].1.close();
                     }
                 }
             }
 
+            // responses go only to the origin, after the changes made by the request
+            for msg in responses.iter() {
+                if self.clients[client_id].1.send(msg.as_str()).is_err() {
+                    self.clients[client_id].1.close();
+                }
+            }
+
             if failed {
-                self.clients[client_id].close();
+                self.clients[client_id].1.close();
             }
         }
 
-        self.clients.retain(|c| c.alive());
+        self.clients.retain(|(_, c)| c.alive());
     }
 
-    pub fn local_command(&mut self, cmd: &str) -> Result<(), Error> {
-        let reply = Reply::new(Vec::new());
-        self.value.command_str(reply.clone(), cmd)?;
+    /// Send the messages scheduled in `reply` to every client.
+    fn broadcast(&mut self, reply: Reply) {
         let reply = reply.into_inner();
 
-        for client in self.clients.iter_mut() {
-            for (msg, _) in reply.iter() {
+        for (_, client) in self.clients.iter_mut() {
+            for (msg, _) in reply.iter().filter(|&&(_, audience)| audience.includes(false)) {
                 if client.send(msg.as_str()).is_err() {
                     client.close();
                 }
             }
         }
+    }
 
+    pub fn local_command(&mut self, cmd: &str) -> Result<(), Error> {
+        let reply = Reply::new(Vec::new());
+        self.value.command_str(reply.clone(), cmd)?;
+        self.broadcast(reply);
         Ok(())
     }
 
     pub fn command(&mut self, cmd: &str) -> Result<(), Error> {
+        // splices are numbered, so that clients can tell which ones they have seen
+        if splice::is_splice(cmd) {
+            let reply = Reply::new(Vec::new());
+            let message = splice::revision(self.splices.revision(), cmd);
+            self.splices.execute(&mut self.value, None, &reply, &message)?;
+            self.broadcast(reply);
+            return Ok(());
+        }
+
         self.value.command_str((), cmd)?;
 
-        for client in self.clients.iter_mut() {
+        for (_, client) in self.clients.iter_mut() {
             if client.send(cmd).is_err() {
                 client.close();
             }
@@ -101,4 +142,3 @@ impl<T: Reflect + Serialize, R: Remote> SharedServer<T, R> {
         self.clients.len()
     }
 }
-
@@ -0,0 +1,146 @@
+use super::*;
+use crate::reply::{Audience, Reply};
+use std::collections::VecDeque;
+
+/// Splices are sent together with a revision of the splice history of the server.
+/// Clients send the revision they have seen, so the server can transform splices that were made
+///  before the client received the latest ones. Servers send the revision the splice created.
+/// A message without a command only tells a new client the current revision.
+/// syntax: `rev:{revision}:{command}`
+const REVISION: &str = "rev:";
+
+/// Number of splices a server remembers. Splices based on older revisions are rejected.
+pub const SPLICE_HISTORY: usize = 1024;
+
+pub(crate) fn revision(revision: u64, command: &str) -> String {
+    format!("{}{}:{}", REVISION, revision, command)
+}
+
+/// Parse a revision message into its revision and command.
+/// Returns `None` if the message has no revision.
+pub(crate) fn parse_revision(message: &str) -> Option<(u64, &str)> {
+    request::split_id(message, REVISION)
+}
+
+/// Returns whether `command` is a splice, which clients send with their revision.
+pub(crate) fn is_splice(command: &str) -> bool {
+    match Command::parse(command) {
+        Ok(command) => matches!(command.split_path().1, Command::Splice { .. }),
+        Err(_) => false,
+    }
+}
+
+/// Transform a splice so that it keeps its intent when `applied` was executed right before it.
+/// Only splices on the same path affect each other; other commands are returned unchanged.
+/// Text that `applied` inserted at the same offset ends up after the text of `command`, and
+///  text that both delete is only deleted once.
+pub fn transform(command: &Command, applied: &Command) -> Command {
+    let (path, splice) = command.split_path();
+    let (applied_path, applied) = applied.split_path();
+
+    match (splice, applied) {
+        (Command::Splice { offset, delete, insert },
+         Command::Splice { offset: at, delete: removed, insert: inserted }) if path == applied_path => {
+            let (offset, delete, at, removed) = (*offset, *delete, *at, *removed);
+            let inserted = inserted.chars().count();
+            let removed_end = at.saturating_add(removed);
+            let end = offset.saturating_add(delete);
+
+            let start = if offset <= at {
+                offset
+            } else if offset >= removed_end {
+                (offset - removed).saturating_add(inserted)
+            } else {
+                at + inserted
+            };
+            let end = if end <= at {
+                end
+            } else if end >= removed_end {
+                (end - removed).saturating_add(inserted)
+            } else {
+                at
+            };
+
+            let splice = Command::Splice { offset: start, delete: end.saturating_sub(start), insert: insert.clone() };
+            Command::with_path(&path, splice)
+        },
+        _ => command.clone(),
+    }
+}
+
+/// The splices applied by a server, with the remote that sent them.
+#[derive(Default)]
+pub(crate) struct SpliceLog {
+    revision: u64,
+    history: VecDeque<(Option<u64>, Command)>,
+}
+
+/// Execute a command without a revision with `reply` as context. Changes that don't call
+///  functions are scheduled for every remote as they are, since the remotes have to see them to
+///  stay in sync, and functions schedule the changes they make themselves.
+/// Batches that mix both are split up, within a batch of their own. This doesn't work for
+///  wildcards, whose changes are only sent as far as functions send them.
+fn execute_plain<T: Reflect + Serialize>(value: &mut T, reply: &Reply, command: &Command) -> Result<(), Error> {
+    if !command.calls() {
+        value.command(reply.clone(), command)?;
+        if command.is_mutation() {
+            reply.push(command.to_string(), Audience::All);
+        }
+        return Ok(());
+    }
+
+    match command.split_path() {
+        (path, Command::Batch { commands }) if commands.iter().any(|command| !command.calls()) => {
+            let commands: Vec<Command> = commands.iter()
+                .map(|command| Command::with_path(&path, command.clone()))
+                .collect();
+            execute_batch(value, reply.clone(), &commands, |value, reply, command| execute_plain(value, &reply, command))
+        },
+        _ => value.command(reply.clone(), command),
+    }
+}
+
+impl SpliceLog {
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Transform a splice that `origin` made at `revision` against the splices that it had not
+    ///  received yet. Splices of the origin itself are skipped, since it made them earlier.
+    fn transform(&self, origin: Option<u64>, revision: u64, command: Command) -> Result<Command, Error> {
+        let missed = self.revision.checked_sub(revision).ok_or(Error::InvalidCommand)? as usize;
+        if missed > self.history.len() {
+            return Err(Error::Command("splice is based on a revision that is no longer remembered".into()));
+        }
+
+        Ok(self.history.iter()
+            .skip(self.history.len() - missed)
+            .filter(|&&(applied_origin, _)| applied_origin != origin)
+            .fold(command, |command, (_, applied)| transform(&command, applied)))
+    }
+
+    fn push(&mut self, origin: Option<u64>, command: Command) -> u64 {
+        if self.history.len() == SPLICE_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((origin, command));
+        self.revision += 1;
+        self.revision
+    }
+
+    /// Execute a message from `origin`. Splices with a revision are transformed, applied, and
+    ///  scheduled in `reply` for every remote with the new revision. Other messages are executed
+    ///  with `reply` as context, as described in `execute_plain`.
+    pub fn execute<T: Reflect + Serialize>(&mut self, value: &mut T, origin: Option<u64>, reply: &Reply, message: &str) -> Result<(), Error> {
+        let (revision, command) = match parse_revision(message) {
+            Some(revision) => revision,
+            None => return execute_plain(value, reply, &Command::parse(message)?),
+        };
+
+        let command = self.transform(origin, revision, Command::parse(command)?)?;
+        value.command((), &command)?;
+        let revision = self.push(origin, command.clone());
+        reply.push(self::revision(revision, &command.to_string()), Audience::All);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use super::*;
+use serde_json::from_value;
+
+/// Tuples have a path element for every position, like `0/` and `1/`, the same as the unnamed
+///  fields of a struct.
+macro_rules! tuple {
+    ($($p:ident $i:tt),*) => {
+        impl<$($p: Reflect + Serialize),*> Reflect for ($($p,)*) {
+            fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
+                match command {
+                    Command::Path { element, command } => {
+                        let mut result = Err(Error::PathError);
+                        $(
+                            if element == stringify!($i) {
+                                let elem = &mut self.$i;
+                                context.with_inner(element.as_str(), |c| result = elem.command(c, command));
+                            }
+                        )*
+                        result
+                    },
+                    Command::Each { elements, command } => {
+                        let children = |_: &Self| Ok(vec![$(stringify!($i).to_string()),*]);
+                        apply_each(self, context, elements, children, command)
+                    },
+                    Command::Set { value } => {
+                        let value = from_value(value.clone())?;
+                        check_replace(self, &context, &value)?;
+                        *self = value;
+                        Ok(())
+                    },
+                    Command::Get => apply_get(self, context),
+                    Command::Cas { expected, value } => apply_cas(self, &context, expected, value),
+                    Command::Batch { commands } => apply_batch(self, context, commands),
+                    &_ => Err(Error::IncompatibleCommand),
+                }
+            }
+
+            fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+                let mut commands = Vec::new();
+                $( diff_child(stringify!($i), &self.$i, &new.$i, &mut commands)?; )*
+                Ok(commands)
+            }
+
+            fn check_readonly(&self, new: &Self) -> Result<(), Error> {
+                $( self.$i.check_readonly(&new.$i)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+tuple!(T0 0);
+tuple!(T0 0, T1 1);
+tuple!(T0 0, T1 1, T2 2);
+tuple!(T0 0, T1 1, T2 2, T3 3);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12, T13 13);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12, T13 13, T14 14);
+tuple!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12, T13 13, T14 14, T15 15);
@@ -0,0 +1,222 @@
+use super::*;
+use crate::list::index;
+use crate::reply::Reply;
+use crate::value::object_key;
+
+/// Returns the negation of a json number, if it has one.
+fn negate(number: &Value) -> Option<Value> {
+    match number.as_i64() {
+        Some(number) => number.checked_neg().map(Value::from),
+        None => number.as_f64().filter(|_| number.is_f64()).map(|number| Value::from(-number)),
+    }
+}
+
+/// Returns the command that undoes `command`, given the `value` it is about to be executed on.
+/// Commands with a natural opposite, like `push` and `pop`, `insert` and `remove` or adding a
+///  negative delta, get that opposite, so they don't undo changes that others made in the
+///  meantime. Other commands are undone by setting the element at the end of their path back to
+///  its current value, which also resets parts of it that are not serialized, like `Hidden` values.
+pub fn inverse<R: Reflect>(value: &mut R, command: &Command) -> Result<Command, Error> {
+    let (path, command) = command.split_path();
+
+    let reply = Reply::new(Vec::new());
+    value.command(reply.clone(), &Command::with_path(&path, Command::Get))?;
+    let old = reply.take_response().ok_or(Error::IncompatibleCommand)?;
+
+    // lists and sets both serialize as arrays, but only lists have paths to their elements
+    let mut first = path.clone();
+    first.push("0");
+    let is_list = value.command((), &Command::with_path(&first, Command::Get)).is_ok();
+    let nothing = Command::Batch { commands: Vec::new() };
+
+    let inverse = match (command, &old) {
+        (command, _) if !command.is_mutation() => nothing,
+        (Command::Push { .. }, _) => Command::Pop,
+        (Command::Pop, Value::Array(list)) => match list.last() {
+            Some(last) => Command::Push { value: last.clone() },
+            None => nothing,
+        },
+        // an empty set and an empty list both undo an insert by removing the key
+        (Command::Insert { key, .. }, Value::Array(list)) if is_list || list.is_empty() => {
+            Command::Remove { key: key.clone() }
+        },
+        // inserting an element that a set already has changes nothing
+        (Command::Insert { key, .. }, Value::Array(set)) => {
+            if set.contains(key) {
+                nothing
+            } else {
+                Command::Remove { key: key.clone() }
+            }
+        },
+        (Command::Insert { key, .. }, Value::Object(map)) => match map.get(&object_key(key)) {
+            Some(old) => Command::Insert { key: key.clone(), value: old.clone() },
+            None => Command::Remove { key: key.clone() },
+        },
+        // `remove:null` empties an option, which is undone by setting it back
+        (Command::Remove { key: Value::Null }, _) => Command::Set { value: old },
+        (Command::Remove { key }, Value::Array(list)) if is_list => match index(key, list.len()) {
+            Ok(index) => Command::Insert { key: key.clone(), value: list[index].clone() },
+            Err(_) => nothing,
+        },
+        (Command::Remove { key }, Value::Array(set)) => {
+            if set.contains(key) {
+                Command::Insert { key: key.clone(), value: Value::Null }
+            } else {
+                nothing
+            }
+        },
+        (Command::Remove { key }, Value::Object(map)) => match map.get(&object_key(key)) {
+            Some(old) => Command::Insert { key: key.clone(), value: old.clone() },
+            None => nothing,
+        },
+        (Command::Add { value }, _) if negate(value).is_some() => Command::Add { value: negate(value).unwrap() },
+        (Command::Swap { a, b }, _) => Command::Swap { a: *a, b: *b },
+        (Command::Move { from, to }, _) => Command::Move { from: *to, to: *from },
+        (Command::Splice { offset, delete, insert }, Value::String(text)) => Command::Splice {
+            offset: *offset,
+            delete: insert.chars().count(),
+            insert: text.chars().skip(*offset).take(*delete).collect(),
+        },
+        (_, _) => Command::Set { value: old },
+    };
+
+    Ok(Command::with_path(&path, inverse))
+}
+
+/// History of commands and the commands that undo them, to undo and redo changes.
+/// Undoing and redoing produce ordinary commands, to be executed the same way as the originals.
+#[derive(Clone, Debug, Default)]
+pub struct UndoStack {
+    undo: Vec<(Command, Command)>,
+    redo: Vec<(Command, Command)>,
+}
+
+impl UndoStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `command`, right before it is executed on `value`. This clears the redo history.
+    pub fn record<R: Reflect>(&mut self, value: &mut R, command: &Command) -> Result<(), Error> {
+        let inverse = inverse(value, command)?;
+        self.undo.push((command.clone(), inverse));
+        self.redo.clear();
+        Ok(())
+    }
+
+    /// Returns the command that undoes the last recorded change, if there is one.
+    pub fn undo(&mut self) -> Option<Command> {
+        let change = self.undo.pop()?;
+        let inverse = change.1.clone();
+        self.redo.push(change);
+        Some(inverse)
+    }
+
+    /// Returns the command that redoes the last undone change, if there is one.
+    pub fn redo(&mut self) -> Option<Command> {
+        let change = self.redo.pop()?;
+        let command = change.0.clone();
+        self.undo.push(change);
+        Some(command)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, HashMap};
+    use futures::Future;
+    use crate::tests::{pipe, Hero, Wallet};
+
+    #[test]
+    fn undo() {
+        let mut hero = Hero { inventory: vec!["sword".into(), "shield".into()], equipment: vec![] };
+        let commands = ["inventory/pop:", "equipment/push:\"shield\"", "inventory/insert:0 \"bow\"",
+                        "inventory/move:0 1", "inventory/0/splice:1 2 \"a\"", "inventory/remove:1", "equipment/set:[]"];
+        let mut inverses = Vec::new();
+        for command in commands.iter() {
+            inverses.push(hero.command_with_inverse((), &Command::parse(command).unwrap()).unwrap());
+        }
+        assert_eq!(inverses[0].to_string(), "inventory/push:\"shield\"");
+        assert_eq!(inverses[1].to_string(), "equipment/pop:");
+        assert_eq!(inverses[2].to_string(), "inventory/remove:0");
+        assert_eq!(inverses[4].to_string(), "inventory/0/splice:1 1 \"wo\"");
+        assert_eq!(inverses[5].to_string(), "inventory/insert:1 \"bow\"");
+        for inverse in inverses.iter().rev() {
+            hero.command((), inverse).unwrap();
+        }
+        assert_eq!(hero, Hero { inventory: vec!["sword".into(), "shield".into()], equipment: vec![] });
+
+        fn inverse_of<R: Reflect>(value: &mut R, command: &str) -> String {
+            value.command_with_inverse((), &Command::parse(command).unwrap()).unwrap().to_string()
+        }
+        let mut wallet = Wallet { gold: 10 };
+        assert_eq!(inverse_of(&mut wallet, "gold/add:1"), "gold/add:-1");
+        assert_eq!(inverse_of(&mut wallet, "gold/mul:2"), "gold/set:11");
+        assert_eq!(inverse_of(&mut vec![7u32, 8], "insert:1 7"), "remove:1");
+        let mut set: BTreeSet<u32> = vec![1, 2].into_iter().collect();
+        assert_eq!(inverse_of(&mut set, "insert:5"), "remove:5");
+        assert_eq!(inverse_of(&mut set, "insert:1"), "batch:[]");
+        let mut map: HashMap<String, u32> = vec![("a".to_string(), 1)].into_iter().collect();
+        assert_eq!(inverse_of(&mut map, "insert:\"a\" 5"), "insert:\"a\" 1");
+        assert_eq!(inverse_of(&mut map, "insert:\"b\" 2"), "remove:\"b\"");
+        assert_eq!(inverse_of(&mut map, "remove:\"a\""), "insert:\"a\" 5");
+        assert_eq!(inverse_of(&mut vec![7u32, 8], "remove:1"), "insert:1 8");
+        assert_eq!(inverse_of(&mut set, "remove:2"), "insert:2");
+        assert_eq!(inverse_of(&mut Some(vec![1u8]), "remove:null"), "set:[1]");
+
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(Wallet { gold: 10 }, accept);
+        let (a, b) = pipe();
+        let (c, d) = pipe();
+        listener.send(a).unwrap();
+        listener.send(c).unwrap();
+        server.update();
+        let mut client = Client::<Wallet, _>::new(b).wait().unwrap();
+        let mut observer = Client::<Wallet, _>::new(d).wait().unwrap();
+
+        // every client undoes its own changes, and the others see ordinary commands
+        let mut history = UndoStack::new();
+        client.command_undoable(&mut history, "call:spend:4").unwrap();
+        server.update();
+        client.update();
+        client.command_undoable(&mut history, "gold/add:1").unwrap();
+        server.update();
+        client.update();
+        observer.update();
+        assert_eq!((server.gold, client.gold, observer.gold), (7, 7, 7));
+
+        assert!(client.undo(&mut history).unwrap());
+        assert!(client.undo(&mut history).unwrap());
+        assert!(!client.undo(&mut history).unwrap());
+        server.update();
+        client.update();
+        observer.update();
+        assert_eq!((server.gold, client.gold, observer.gold), (10, 10, 10));
+
+        assert!(client.redo(&mut history).unwrap());
+        server.update();
+        observer.update();
+        assert_eq!((server.gold, observer.gold), (6, 6));
+        assert!(history.can_undo() && history.can_redo());
+
+        // undoing an add keeps what others added in the meantime
+        client.update();
+        client.command_undoable(&mut history, "gold/add:1").unwrap();
+        observer.command("gold/add:5").unwrap();
+        server.update();
+        client.update();
+        assert!(client.undo(&mut history).unwrap());
+        server.update();
+        observer.update();
+        assert_eq!((server.gold, observer.gold), (11, 11));
+    }
+}
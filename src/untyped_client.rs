@@ -0,0 +1,254 @@
+use super::*;
+
+use std::mem;
+use std::ops::Deref;
+use futures::*;
+
+/// A client that keeps its copy of the value as json, so it can connect to any server without
+///  knowing the type of its value.
+/// The commands sent by the server are executed on the json value, except for calls, which need
+///  the methods of the real type. These are kept as events instead, to be read with `events`.
+/// Some commands can't be executed without the type, like inserts into sets, which serialize as
+///  arrays. Their errors are kept to be read with `errors`, and the client requests the whole
+///  value from the server to replace its copy.
+pub struct UntypedClient<R: Remote> {
+    client: Client<Value, R>,
+    events: Vec<Command>,
+    errors: Vec<Error>,
+    /// The whole value requested after an error. Messages received before it are part of it.
+    resync: Option<Response>,
+}
+
+impl<R: Remote> Deref for UntypedClient<R> {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.client
+    }
+}
+
+/// Takes the calls out of `command` and adds them to `events`, with the path that leads to them.
+/// Returns what is left of `command`, if there is anything.
+fn take_calls(command: Command, events: &mut Vec<Command>) -> Option<Command> {
+    match command {
+        Command::Call { .. } => {
+            events.push(command);
+            None
+        },
+        Command::Path { element, command } => {
+            let path = |command| Command::Path { element: element.clone(), command: Box::new(command) };
+            take_inner_calls(*command, events, path)
+        },
+        Command::Each { elements, command } => {
+            let each = |command| Command::Each { elements: elements.clone(), command: Box::new(command) };
+            take_inner_calls(*command, events, each)
+        },
+        Command::Batch { commands } => Some(Command::Batch {
+            commands: commands.into_iter().filter_map(|command| take_calls(command, events)).collect(),
+        }),
+        command => Some(command),
+    }
+}
+
+/// Takes the calls out of the command inside `outer`, and wraps both the calls and what is left
+///  in `outer` again.
+fn take_inner_calls<F: Fn(Command) -> Command>(inner: Command, events: &mut Vec<Command>, outer: F) -> Option<Command> {
+    let start = events.len();
+    let rest = take_calls(inner, events);
+    for event in events[start..].iter_mut() {
+        *event = outer(mem::replace(event, Command::Pop));
+    }
+    rest.map(outer)
+}
+
+/// Executes `command` on the json copy of a typed value.
+/// `Option` values serialize as their contents or `null`, so their `val` element leads to the
+///  value itself and `remove:null` replaces it with `null`. Both only apply where the json value
+///  can't mean something else, so other commands keep the semantics of `Value`.
+fn follow(value: &mut Value, command: &Command) -> Result<(), Error> {
+    match command {
+        Command::Path { element, command } => {
+            if element == "val" && !value.is_null() && value.get("val").is_none() {
+                return follow(value, command);
+            }
+            let child = match value {
+                Value::Object(object) => object.get_mut(&value::element_key(element)),
+                Value::Array(array) => element.parse().ok().and_then(move |index: usize| array.get_mut(index)),
+                _ => None,
+            };
+            follow(child.ok_or(Error::PathError)?, command)
+        },
+        Command::Each { elements: Some(elements), command } => elements.iter().try_for_each(|element| {
+            follow(value, &Command::Path { element: element.clone(), command: command.clone() })
+        }),
+        Command::Batch { commands } => commands.iter().try_for_each(|command| follow(value, command)),
+        Command::Remove { key: Value::Null } if !value.is_array() => {
+            *value = Value::Null;
+            Ok(())
+        },
+        command => value.command((), command),
+    }
+}
+
+/// Replaces `value` with the requested whole value once it has been received.
+fn take_resync(value: &mut Value, resync: &mut Option<Response>, errors: &mut Vec<Error>) {
+    if let Some(result) = resync.as_mut().and_then(Response::try_take) {
+        *resync = None;
+        match result {
+            Ok(state) => *value = state,
+            Err(error) => errors.push(error),
+        }
+    }
+}
+
+impl<R: Remote> UntypedClient<R> {
+    pub fn new(remote: R) -> impl Future<Item=UntypedClient<R>, Error=Error> {
+        Client::new(remote).map(|client| UntypedClient { client, events: Vec::new(), errors: Vec::new(), resync: None })
+    }
+
+    pub fn alive(&self) -> bool {
+        self.client.alive()
+    }
+
+    pub fn update(&mut self) {
+        let (events, errors, resync) = (&mut self.events, &mut self.errors, &mut self.resync);
+        let mut failed = false;
+        self.client.receive(|value, cmd| {
+            take_resync(value, resync, errors);
+            let command = match Command::parse(cmd) {
+                Ok(command) => command,
+                Err(error) => {
+                    errors.push(error);
+                    failed = true;
+                    return;
+                },
+            };
+            let command = if command.calls() {
+                take_calls(command, events)
+            } else {
+                Some(command)
+            };
+
+            // the requested value already contains the changes sent before it
+            if let (Some(command), None) = (command, resync.as_ref()) {
+                if let Err(error) = follow(value, &command) {
+                    errors.push(error);
+                    failed = true;
+                }
+            }
+        });
+        take_resync(&mut self.client, &mut self.resync, &mut self.errors);
+
+        if failed && self.resync.is_none() {
+            self.resync = Some(self.client.request("get:"));
+        }
+    }
+
+    /// Returns the calls received since the last time, in the order they were received.
+    /// Calls made by clients reach this client as the commands they execute, but calls that the
+    ///  server broadcasts as is, like the ones of `SharedServer::command`, only show up here.
+    pub fn events(&mut self) -> Vec<Command> {
+        mem::take(&mut self.events)
+    }
+
+    /// Returns the errors of the commands that couldn't be executed on the json value since the
+    ///  last time. The value is requested again after these, so it is up to date after the next
+    ///  updates.
+    pub fn errors(&mut self) -> Vec<Error> {
+        mem::take(&mut self.errors)
+    }
+
+    pub fn command(&mut self, cmd: &str) -> Result<(), Error> {
+        self.client.command(cmd)
+    }
+
+    /// Send a command to the server and get a future for its outcome, like `Client::request`.
+    pub fn request(&mut self, cmd: &str) -> Response {
+        self.client.request(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, HashMap};
+    use futures::Future;
+    use crate::tests::{pipe, Tree, Wallet};
+
+    #[derive(Serialize, Deserialize, Reflect)]
+    pub struct Party {
+        players: HashMap<String, Vec<String>>,
+        host: Option<Wallet>,
+    }
+
+    #[test]
+    fn untyped_clients() {
+        let mut players = HashMap::new();
+        players.insert("ann".to_string(), vec!["sword".to_string()]);
+        let (listener, accept) = std::sync::mpsc::channel();
+        let mut server = SharedServer::new(Party { players, host: Some(Wallet { gold: 10 }) }, accept);
+        let (a, b) = pipe();
+        let (c, d) = pipe();
+        listener.send(a).unwrap();
+        listener.send(c).unwrap();
+        server.update();
+        let mut client = Client::<Party, _>::new(b).wait().unwrap();
+        let mut inspector = UntypedClient::new(d).wait().unwrap();
+        assert_eq!(*inspector, serde_json::json!({ "players": { "ann": ["sword"] }, "host": { "gold": 10 } }));
+
+        client.command("players/\"ann\"/push:\"bow\"").unwrap();
+        client.command("players/insert:\"bob\" []").unwrap();
+        client.command("host/val/gold/add:5").unwrap();
+        let spend = client.request("host/val/call:spend:4");
+        server.update();
+        client.update();
+        inspector.update();
+        assert_eq!(spend.wait().unwrap(), Value::from(11));
+        assert_eq!(*inspector, serde_json::to_value(&*server).unwrap());
+        assert!(inspector.events().is_empty());
+
+        // calls that the server broadcasts as is become events
+        server.command("batch:[\"host/val/call:can_afford:1\", \"players/\\\"bob\\\"/push:\\\"axe\\\"\"]").unwrap();
+        inspector.update();
+        let events: Vec<String> = inspector.events().iter().map(Command::to_string).collect();
+        assert_eq!(events, vec!["host/val/call:can_afford:1"]);
+        assert_eq!(inspector["players"]["bob"], serde_json::json!(["axe"]));
+
+        inspector.command("players/\"ann\"/pop:").unwrap();
+        client.command("host/remove:null").unwrap();
+        server.update();
+        inspector.update();
+        assert_eq!(server.players["ann"], vec!["sword".to_string()]);
+        assert_eq!(*inspector, serde_json::to_value(&*server).unwrap());
+        assert!(inspector["host"].is_null());
+
+        // only the untyped client treats json values as options
+        let mut wallet = serde_json::json!({ "gold": 10 });
+        assert!(matches!(wallet.command((), &Command::parse("val/gold/add:5").unwrap()), Err(Error::PathError)));
+        assert!(wallet.command((), &Command::parse("remove:null").unwrap()).is_ok());
+        assert_eq!(wallet, serde_json::json!({ "gold": 10 }));
+    }
+
+    #[test]
+    fn untyped_resyncs() {
+        let (listener, accept) = std::sync::mpsc::channel();
+        let tree = Tree { value: 'a', next: None, tags: BTreeSet::new() };
+        let mut server = SharedServer::new(tree, accept);
+        let (a, b) = pipe();
+        listener.send(a).unwrap();
+        server.update();
+        let mut inspector = UntypedClient::new(b).wait().unwrap();
+
+        // sets serialize as arrays, so the json copy can't insert into them
+        server.command("tags/insert:\"x\"").unwrap();
+        inspector.update();
+        assert_eq!(inspector.errors().len(), 1);
+        server.command("value/set:\"b\"").unwrap();
+        server.update();
+        server.command("next/set:{\"value\":\"c\",\"next\":null,\"tags\":[]}").unwrap();
+        inspector.update();
+        assert!(inspector.errors().is_empty());
+        assert_eq!(*inspector, serde_json::to_value(&*server).unwrap());
+        assert_eq!(inspector["tags"], serde_json::json!(["x"]));
+    }
+}
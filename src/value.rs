@@ -0,0 +1,158 @@
+use super::*;
+use crate::list::{index, move_element, swap};
+use serde_json::{from_str, to_value, Map};
+use std::convert::TryFrom;
+
+/// Returns the object key in a path element, which is either a quoted json string, as used for
+///  the keys of maps, or the key itself.
+pub(crate) fn element_key(element: &str) -> String {
+    from_str::<String>(element).unwrap_or_else(|_| element.to_string())
+}
+
+/// Returns the object key in the key of `insert:` or `remove:`. Keys that are not strings are
+///  used as their json text, so `insert:1 true` sets the key `1`.
+pub(crate) fn object_key(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        key => key.to_string(),
+    }
+}
+
+/// Numbers stay integers as long as both operands are, and become floats otherwise.
+fn arithmetic(number: &Value, command: &Command) -> Result<Value, Error> {
+    let operand = match command {
+        Command::Add { value } | Command::Mul { value } |
+        Command::Min { value } | Command::Max { value } => value,
+        &_ => return Err(Error::IncompatibleCommand),
+    };
+    let integer = |value: &Value| value.as_i64().map(i128::from).or_else(|| value.as_u64().map(i128::from));
+
+    match (integer(number), integer(operand)) {
+        (Some(mut number), Some(_)) => {
+            number.arithmetic(command)?;
+            i64::try_from(number).map(Value::from)
+                .or_else(|_| u64::try_from(number).map(Value::from))
+                .map_err(|_| Error::Overflow)
+        },
+        _ => {
+            let mut number = number.as_f64().ok_or(Error::IncompatibleCommand)?;
+            number.arithmetic(command)?;
+            Ok(number.into())
+        },
+    }
+}
+
+/// Schemaless json documents. Object keys and array indices are path elements, and arrays and
+///  objects support the commands of lists and maps.
+impl Reflect for Value {
+    fn command<C: Context>(&mut self, mut context: C, command: &Command) -> Result<(), Error> {
+        match (command, self) {
+            (Command::Path { element, command }, Value::Object(object)) => {
+                let elem = object.get_mut(&element_key(element)).ok_or(Error::PathError)?;
+                let mut result = Ok(());
+                context.with_inner(element.as_str(), |c| result = elem.command(c, command));
+                result
+            },
+            (Command::Path { element, command }, Value::Array(array)) => {
+                let index: usize = element.parse()?;
+                let elem = array.get_mut(index).ok_or(Error::PathError)?;
+                let mut result = Ok(());
+                context.with_inner(element.as_str(), |c| result = elem.command(c, command));
+                result
+            },
+            (Command::Each { elements, command }, value) => {
+                let children = |value: &Value| match value {
+                    Value::Object(object) => Ok(object.keys().map(|key| Value::String(key.clone()).to_string()).collect()),
+                    Value::Array(array) => Ok((0..array.len()).map(|i| i.to_string()).collect()),
+                    _ => Ok(Vec::new()),
+                };
+                apply_each(value, context, elements, children, command)
+            },
+            (Command::Set { value }, this) => {
+                *this = value.clone();
+                Ok(())
+            },
+            (Command::Push { value }, Value::Array(array)) => {
+                array.push(value.clone());
+                Ok(())
+            },
+            (Command::Pop, Value::Array(array)) => {
+                array.pop();
+                Ok(())
+            },
+            (Command::Insert { key, value }, Value::Array(array)) => {
+                let index = index(key, array.len() + 1)?;
+                array.insert(index, value.clone());
+                Ok(())
+            },
+            (Command::Insert { key, value }, Value::Object(object)) => {
+                object.insert(object_key(key), value.clone());
+                Ok(())
+            },
+            (Command::Remove { key }, Value::Array(array)) => {
+                let index = index(key, array.len())?;
+                array.remove(index);
+                Ok(())
+            },
+            (Command::Remove { key }, Value::Object(object)) => {
+                object.remove(&object_key(key));
+                Ok(())
+            },
+            (Command::Swap { a, b }, Value::Array(array)) => swap(array, *a, *b),
+            (Command::Move { from, to }, Value::Array(array)) => move_element(array, *from, *to),
+            (Command::Truncate { len }, Value::Array(array)) => {
+                array.truncate(*len);
+                Ok(())
+            },
+            (Command::Clear, Value::Array(array)) => {
+                array.clear();
+                Ok(())
+            },
+            (Command::Clear, Value::Object(object)) => {
+                object.clear();
+                Ok(())
+            },
+            (Command::Extend { value: Value::Array(values) }, Value::Array(array)) => {
+                array.extend(values.iter().cloned());
+                Ok(())
+            },
+            (Command::Add { .. }, this) | (Command::Mul { .. }, this) |
+            (Command::Min { .. }, this) | (Command::Max { .. }, this) if this.is_number() => {
+                *this = arithmetic(this, command)?;
+                Ok(())
+            },
+            (Command::Splice { offset, delete, insert }, Value::String(string)) => {
+                string.splice(*offset, *delete, insert)
+            },
+            (Command::Get, this) => apply_get(this, context),
+            (Command::Cas { expected, value }, this) => apply_cas(this, &context, expected, value),
+            (Command::Batch { commands }, this) => apply_batch(this, context, commands),
+            (Command::Path { .. }, _) => Err(Error::PathError),
+            (&_, _) => Err(Error::IncompatibleCommand),
+        }
+    }
+
+    fn diff(&self, new: &Self) -> Result<Vec<Command>, Error> {
+        match (self, new) {
+            (Value::Object(old), Value::Object(new)) => diff_object(old, new),
+            (Value::Array(old), Value::Array(new)) => {
+                diff_list(&old.iter().collect::<Vec<_>>(), &new.iter().collect::<Vec<_>>())
+            },
+            (old, new) => diff_set(old, new),
+        }
+    }
+}
+
+fn diff_object(old: &Map<String, Value>, new: &Map<String, Value>) -> Result<Vec<Command>, Error> {
+    let mut commands = Vec::new();
+    for (key, old) in old.iter() {
+        match new.get(key) {
+            Some(new) => diff_child(&to_value(key)?.to_string(), old, new, &mut commands)?,
+            None => commands.push(Command::Remove { key: Value::String(key.clone()) }),
+        }
+    }
+    for (key, value) in new.iter().filter(|&(key, _)| !old.contains_key(key)) {
+        commands.push(Command::Insert { key: Value::String(key.clone()), value: value.clone() });
+    }
+    Ok(commands)
+}